@@ -1,2 +1,41 @@
+use super::{BackendProfile, ProfileBackend, ProfileInput};
+use crate::tree::CallTree;
+use async_trait::async_trait;
+
 mod parser;
-mod utils;
+pub(crate) mod utils;
+
+/// Builds the call tree from the call trace of the test, down to external calls.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CallTraceBackend;
+
+#[async_trait]
+impl ProfileBackend for CallTraceBackend {
+    fn name(&self) -> &str {
+        "calltrace"
+    }
+
+    fn description(&self) -> &str {
+        "external calls, from the call trace"
+    }
+
+    async fn profile(&self, input: &ProfileInput<'_>) -> eyre::Result<BackendProfile> {
+        // libraries are deployed before the test contract, whose deployment comes last
+        let arena = input
+            .trace
+            .or_else(|| {
+                input
+                    .test_result
+                    .traces
+                    .iter()
+                    .rev()
+                    .find_map(|(kind, arena)| (*kind == input.trace_kind).then_some(arena))
+            })
+            .ok_or_else(|| {
+                eyre::eyre!("no {:?} trace for {}", input.trace_kind, input.test_name)
+            })?;
+
+        let tree = CallTree::from_call_trace(arena.nodes(), input.decoder).await?;
+        Ok(tree.into())
+    }
+}
@@ -1,46 +1,64 @@
 use super::utils;
-use crate::flamegraph::{self, Flamegraph};
-use foundry_evm_traces::{CallTraceDecoder, CallTraceNode};
+use crate::tree::{CallTree, Frame, NodeId};
+use foundry_evm_traces::{CallTraceDecoder, CallTraceNode, DecodedCallTrace};
 
-impl<'a> Flamegraph<'a> {
-    pub async fn from_call_trace(nodes: &[CallTraceNode], decoder: &CallTraceDecoder) -> Self {
-        let mut decoded = vec![];
+impl CallTree {
+    /// Builds the call tree from the nodes of a call trace arena.
+    ///
+    /// Call traces do not tell where a call starts within its parent, so children are laid out
+    /// one after the other from the start of their parent.
+    pub async fn from_call_trace(
+        nodes: &[CallTraceNode],
+        decoder: &CallTraceDecoder,
+    ) -> eyre::Result<Self> {
+        eyre::ensure!(!nodes.is_empty(), "call trace is empty");
 
+        let mut decoded = vec![];
         for node in nodes {
             let function = decoder.decode_function(&node.trace).await;
             decoded.push((node, function));
         }
 
-        let mut folded_stack_lines = vec![];
-        for current in &decoded {
-            let mut stack = vec![];
-            let mut ptr = current;
-            loop {
-                stack.push(ptr);
-                if let Some(parent_idx) = ptr.0.parent {
-                    ptr = &decoded[parent_idx];
-                } else {
-                    break;
-                }
-            }
-
-            let mut line = vec![];
-            while let Some(el) = stack.pop() {
-                line.push(utils::get_display(el));
-            }
+        let mut tree = CallTree::new(frame(decoder, &decoded[0], 0));
+        add_children(&mut tree, decoder, &decoded, 0, tree.root());
+        Ok(tree)
+    }
+}
 
-            let mut gas = current.0.trace.gas_used as i64;
-            for child_idx in &current.0.children {
-                gas -= decoded[*child_idx].0.trace.gas_used as i64;
-            }
+fn frame(
+    decoder: &CallTraceDecoder,
+    current: &(&CallTraceNode, DecodedCallTrace),
+    gas_start: u64,
+) -> Frame {
+    let trace = &current.0.trace;
+    Frame {
+        gas_end: gas_start + trace.gas_used,
+        address: Some(trace.address),
+        revert: (!trace.success).then(|| {
+            decoder
+                .revert_decoder
+                .decode(&trace.output, Some(trace.status))
+        }),
+        ..Frame::new(
+            utils::get_display(current),
+            current.0.trace.kind.into(),
+            gas_start,
+        )
+    }
+}
 
-            let line = [line.join(";"), gas.to_string()].join(" ");
-            folded_stack_lines.push(line);
-        }
-        folded_stack_lines.reverse();
-        Self {
-            folded_stack_lines,
-            options: flamegraph::Options::default(),
-        }
+fn add_children(
+    tree: &mut CallTree,
+    decoder: &CallTraceDecoder,
+    decoded: &[(&CallTraceNode, DecodedCallTrace)],
+    idx: usize,
+    id: NodeId,
+) {
+    let mut child_gas_start = tree.node(id).frame.gas_start;
+    for child_idx in &decoded[idx].0.children {
+        let child = frame(decoder, &decoded[*child_idx], child_gas_start);
+        child_gas_start = child.gas_end;
+        let child_id = tree.push(id, child);
+        add_children(tree, decoder, decoded, *child_idx, child_id);
     }
 }
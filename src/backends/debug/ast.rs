@@ -0,0 +1,261 @@
+use foundry_compilers::{
+    artifacts::ast::{Ast, Node, NodeType},
+    ProjectCompileOutput,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// Source ranges of the functions and modifiers defined in the solidity sources, built from the
+/// solc ast, to name the internal functions jumped into.
+///
+/// Source ids are only unique within a compiler run. Projects compiled with several solc versions
+/// may give the same id to different files, and the debug steps only carry the id, so such ids
+/// are left ambiguous instead of resolved against the wrong file.
+#[derive(Clone, Debug, Default)]
+pub struct FunctionIndex {
+    /// Definitions by source id.
+    definitions: HashMap<u32, Vec<Definition>>,
+    /// Paths of the source files by source id.
+    paths: BTreeMap<u32, PathBuf>,
+    /// Source ids given to more than one file.
+    ambiguous: BTreeSet<u32>,
+}
+
+#[derive(Clone, Debug)]
+struct Definition {
+    start: usize,
+    length: usize,
+    /// Fully qualified name, e.g. `Vault.deposit(uint256,address)`.
+    name: String,
+}
+
+impl FunctionIndex {
+    /// Indexes the asts of the compiler output, and the paths of the sources. The ast has to be
+    /// requested in the config.
+    pub fn from_project_output(output: &ProjectCompileOutput) -> Self {
+        let mut index = Self::default();
+        for (artifact_id, artifact) in output.artifact_ids() {
+            let Some(id) = artifact.id else {
+                continue;
+            };
+            // every contract of a file carries the ast of the whole file
+            if index.add_path(id, &artifact_id.source) {
+                if let Some(ast) = &artifact.ast {
+                    index.add_ast(id, ast);
+                }
+            }
+        }
+        index
+    }
+
+    /// Records that the source `id` is the file at `path`, and returns whether the file is new
+    /// to the index. A second file with the same id makes the id ambiguous.
+    fn add_path(&mut self, id: u32, path: &Path) -> bool {
+        if self.ambiguous.contains(&id) {
+            return false;
+        }
+        match self.paths.get(&id) {
+            None => {
+                self.paths.insert(id, path.to_path_buf());
+                true
+            }
+            Some(known) if known == path => false,
+            Some(_) => {
+                self.paths.remove(&id);
+                self.definitions.remove(&id);
+                self.ambiguous.insert(id);
+                false
+            }
+        }
+    }
+
+    /// Returns whether the source `id` was given to more than one file.
+    pub fn is_ambiguous(&self, id: u32) -> bool {
+        self.ambiguous.contains(&id)
+    }
+
+    /// Returns the source ids given to more than one file.
+    pub fn ambiguous(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ambiguous.iter().copied()
+    }
+
+    /// Returns the paths of the source files by source id, relative to `root`, without the
+    /// ambiguous ids.
+    pub fn source_paths(&self, root: &Path) -> BTreeMap<u32, PathBuf> {
+        self.paths
+            .iter()
+            .map(|(id, path)| (*id, path.strip_prefix(root).unwrap_or(path).to_path_buf()))
+            .collect()
+    }
+
+    fn add_ast(&mut self, id: u32, ast: &Ast) {
+        let definitions = self.definitions.entry(id).or_default();
+        for node in &ast.nodes {
+            match node.node_type {
+                NodeType::ContractDefinition => {
+                    let contract = attribute(node, "name").unwrap_or_default();
+                    for node in &node.nodes {
+                        if let Some(definition) = definition(Some(contract), node) {
+                            definitions.push(definition);
+                        }
+                    }
+                }
+                // free functions
+                _ => definitions.extend(definition(None, node)),
+            }
+        }
+    }
+
+    /// Returns the name of the innermost function or modifier containing `offset` in the source
+    /// `id`.
+    pub fn find(&self, id: u32, offset: usize) -> Option<&str> {
+        self.definitions
+            .get(&id)?
+            .iter()
+            .filter(|definition| {
+                definition.start <= offset && offset < definition.start + definition.length
+            })
+            .min_by_key(|definition| definition.length)
+            .map(|definition| definition.name.as_str())
+    }
+}
+
+#[cfg(test)]
+impl FunctionIndex {
+    /// Adds the definition of `name` in the source `id`, spanning `length` bytes from `start`.
+    pub(crate) fn define(&mut self, id: u32, start: usize, length: usize, name: &str) {
+        self.definitions.entry(id).or_default().push(Definition {
+            start,
+            length,
+            name: name.to_string(),
+        });
+    }
+}
+
+fn attribute<'a>(node: &'a Node, name: &str) -> Option<&'a str> {
+    node.other.get(name)?.as_str()
+}
+
+fn definition(contract: Option<&str>, node: &Node) -> Option<Definition> {
+    if !matches!(
+        node.node_type,
+        NodeType::FunctionDefinition | NodeType::ModifierDefinition
+    ) {
+        return None;
+    }
+
+    // constructors, fallback and receive functions have no name
+    let name = attribute(node, "name")
+        .filter(|name| !name.is_empty())
+        .or_else(|| attribute(node, "kind"))?;
+    let types = node
+        .other
+        .get("parameters")
+        .and_then(|parameters| parameters.get("parameters"))
+        .and_then(|parameters| parameters.as_array())
+        .map(|parameters| {
+            parameters
+                .iter()
+                .map(|parameter| {
+                    parameter
+                        .pointer("/typeDescriptions/typeString")
+                        .and_then(|type_string| type_string.as_str())
+                        .map(canonical_type)
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .unwrap_or_default();
+
+    Some(Definition {
+        start: node.src.start,
+        length: node.src.length.unwrap_or_default(),
+        name: match contract {
+            Some(contract) => format!("{contract}.{name}({types})"),
+            None => format!("{name}({types})"),
+        },
+    })
+}
+
+/// Strips the data location and the kind of user defined types from a solc type string, e.g.
+/// `struct Vault.Position memory` becomes `Vault.Position`.
+fn canonical_type(type_string: &str) -> String {
+    let type_string = ["contract ", "struct ", "enum "]
+        .iter()
+        .find_map(|prefix| type_string.strip_prefix(prefix))
+        .unwrap_or(type_string);
+    [" storage ref", " storage pointer", " memory", " calldata"]
+        .iter()
+        .find_map(|suffix| type_string.strip_suffix(suffix))
+        .unwrap_or(type_string)
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_innermost() {
+        let mut index = FunctionIndex::default();
+        index.definitions.insert(
+            0,
+            vec![
+                Definition {
+                    start: 0,
+                    length: 100,
+                    name: "Vault.deposit(uint256)".to_string(),
+                },
+                Definition {
+                    start: 20,
+                    length: 30,
+                    name: "Vault.onlyOwner()".to_string(),
+                },
+            ],
+        );
+        assert_eq!(index.find(0, 10), Some("Vault.deposit(uint256)"));
+        assert_eq!(index.find(0, 25), Some("Vault.onlyOwner()"));
+        assert_eq!(index.find(0, 100), None);
+        assert_eq!(index.find(1, 10), None);
+    }
+
+    #[test]
+    fn test_ambiguous_source_id() {
+        let mut index = FunctionIndex::default();
+        assert!(index.add_path(0, Path::new("/project/src/Vault.sol")));
+        assert!(!index.add_path(0, Path::new("/project/src/Vault.sol")));
+        assert!(index.add_path(1, Path::new("/project/src/Token.sol")));
+        index.definitions.insert(
+            0,
+            vec![Definition {
+                start: 0,
+                length: 100,
+                name: "Vault.deposit(uint256)".to_string(),
+            }],
+        );
+
+        // another compiler run gave the id to another file
+        assert!(!index.add_path(0, Path::new("/project/lib/Old.sol")));
+        assert!(!index.add_path(0, Path::new("/project/src/Vault.sol")));
+        assert!(index.is_ambiguous(0));
+        assert_eq!(index.find(0, 10), None);
+        assert_eq!(
+            index.source_paths(Path::new("/project")),
+            BTreeMap::from([(1, PathBuf::from("src/Token.sol"))])
+        );
+    }
+
+    #[test]
+    fn test_canonical_type() {
+        assert_eq!(canonical_type("uint256"), "uint256");
+        assert_eq!(
+            canonical_type("struct Vault.Position memory"),
+            "Vault.Position"
+        );
+        assert_eq!(canonical_type("contract IERC20"), "IERC20");
+        assert_eq!(canonical_type("uint256[] calldata"), "uint256[]");
+    }
+}
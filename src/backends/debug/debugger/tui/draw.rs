@@ -1,4 +1,7 @@
-use super::super::super::step::{Step, VecStep};
+use super::super::super::{
+    diagnostic::{Diagnostic, DiagnosticKind},
+    step::{Step, VecStep},
+};
 use super::context::DebuggerContext;
 use foundry_compilers::sourcemap::SourceElement;
 use revm_inspectors::tracing::types::CallKind;
@@ -27,7 +30,17 @@ impl DebuggerContext<'_> {
     fn src_text(&self, steps: &mut VecStep) {
         let (source_element, source_code) = match self.src_map() {
             Ok(r) => r,
-            Err(_) => return,
+            Err(kind) => {
+                let address = *self.address();
+                steps.skip(Diagnostic {
+                    kind,
+                    address,
+                    contract: self.debugger.identified_contracts.get(&address).cloned(),
+                    pc: self.current_step().pc,
+                    source: None,
+                });
+                return;
+            }
         };
 
         let offset = source_element.offset;
@@ -42,24 +55,26 @@ impl DebuggerContext<'_> {
             source_element: source_element.clone(),
             source_code: source_code[actual_start..actual_end].to_string(),
             current_step: self.current_step().clone(),
+            address: *self.address(),
+            call_kind: self.call_kind(),
         };
         steps.push(new_step);
     }
 
-    fn src_map(&self) -> Result<(SourceElement, &str), String> {
+    fn src_map(&self) -> Result<(SourceElement, &str), DiagnosticKind> {
         let address = self.address();
         let Some(contract_name) = self.debugger.identified_contracts.get(address) else {
-            return Err(format!("Unknown contract at address {address}"));
+            return Err(DiagnosticKind::UnknownContract);
         };
 
         let Some(mut files_source_code) =
             self.debugger.contracts_sources.get_sources(contract_name)
         else {
-            return Err(format!("No source map index for contract {contract_name}"));
+            return Err(DiagnosticKind::NoSourceMap);
         };
 
         let Some((create_map, rt_map)) = self.debugger.pc_ic_maps.get(contract_name) else {
-            return Err(format!("No PC-IC maps for contract {contract_name}"));
+            return Err(DiagnosticKind::NoSourceMap);
         };
 
         let is_create = matches!(self.call_kind(), CallKind::Create | CallKind::Create2);
@@ -92,7 +107,7 @@ impl DebuggerContext<'_> {
                     })
             })
         else {
-            return Err(format!("No source map for contract {contract_name}"));
+            return Err(DiagnosticKind::UnmappedPc);
         };
 
         Ok((source_element, source_code))
@@ -0,0 +1,159 @@
+use crate::profile::SourceLocation;
+use alloy_primitives::Address;
+use std::fmt;
+
+/// A problem met while building the call tree from the debug steps.
+///
+/// The call tree is still built, but the frames around the diagnostic may be missing or
+/// misplaced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Address of the contract being executed.
+    pub address: Address,
+    /// Name of the contract being executed, if it was identified.
+    pub contract: Option<String>,
+    pub pc: usize,
+    pub source: Option<SourceLocation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The first step is not at the start of a call.
+    UnexpectedStart { gas: u64 },
+    /// No contract was identified at the address, its steps are skipped.
+    UnknownContract,
+    /// The contract has no source map, its steps are skipped.
+    NoSourceMap,
+    /// The pc is not in the source map of the contract, the step is skipped.
+    UnmappedPc,
+    /// A jump out of an internal function that was never jumped into.
+    UnmatchedJumpOut,
+    /// A return or revert while no call is open.
+    UnmatchedReturn,
+    /// The trace ends right after a jump or call.
+    TruncatedTrace,
+    /// The source id was given to several files by different compiler runs, so the function
+    /// jumped into is named from the source snippet.
+    AmbiguousSource { index: u32 },
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::UnexpectedStart { gas } => {
+                write!(f, "trace starts {gas} gas into the call")
+            }
+            DiagnosticKind::UnknownContract => write!(f, "unknown contract"),
+            DiagnosticKind::NoSourceMap => write!(f, "no source map"),
+            DiagnosticKind::UnmappedPc => write!(f, "pc not in the source map"),
+            DiagnosticKind::UnmatchedJumpOut => write!(f, "unmatched jump out"),
+            DiagnosticKind::UnmatchedReturn => write!(f, "unmatched return"),
+            DiagnosticKind::TruncatedTrace => write!(f, "trace ends in the middle of a call"),
+            DiagnosticKind::AmbiguousSource { index } => {
+                write!(f, "source id {index} is used by more than one compiler run")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at pc {} in ", self.kind, self.pc)?;
+        match &self.contract {
+            Some(contract) => write!(f, "{contract} ({})", self.address)?,
+            None => write!(f, "{}", self.address)?,
+        }
+        if let Some(line) = self.source.and_then(|source| source.line) {
+            write!(f, ", line {line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backends::debug::{
+        ast::FunctionIndex,
+        parser::parse_steps,
+        step::{Step, VecStep},
+    };
+    use alloy_primitives::U256;
+    use foundry_compilers::sourcemap::Jump;
+    use foundry_evm_traces::CallTraceDecoderBuilder;
+
+    #[test]
+    fn test_display() {
+        let diagnostic = Diagnostic {
+            kind: DiagnosticKind::UnmatchedJumpOut,
+            address: Address::repeat_byte(0x11),
+            contract: Some("Vault".to_string()),
+            pc: 42,
+            source: Some(SourceLocation {
+                index: Some(0),
+                offset: 10,
+                length: 5,
+                line: Some(7),
+            }),
+        };
+        assert_eq!(
+            diagnostic.to_string(),
+            "unmatched jump out at pc 42 in Vault (0x1111111111111111111111111111111111111111), line 7"
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let vault = Address::repeat_byte(0x11);
+        let unknown = Address::repeat_byte(0x22);
+        let mut decoder = CallTraceDecoderBuilder::new().build();
+        decoder
+            .contracts
+            .insert(vault, "src/Vault.sol:Vault".to_string());
+
+        let mut jump_out = Step::mock(vault, "}", 0x56, 8);
+        jump_out.source_element.jump = Jump::Out;
+        // a call without source, to a contract that is neither identified nor named in the source
+        let mut call = Step::mock(vault, "", 0xF1, 15);
+        call.current_step.stack = vec![U256::ZERO; 5];
+        call.current_step
+            .stack
+            .extend([U256::from_be_slice(unknown.as_slice()), U256::from(50_000)]);
+        let mut steps = vec![
+            // the trace misses the start of the call
+            Step::mock(vault, "contract Vault {", 0x5B, 5),
+            jump_out,
+            // the call ends before the end of the trace
+            Step::mock(vault, "contract Vault {", 0x00, 12),
+            call,
+            Step::mock(vault, "}", 0x00, 30),
+        ];
+        for (pc, step) in steps.iter_mut().enumerate() {
+            step.current_step.pc = pc;
+        }
+        let steps = VecStep {
+            steps,
+            diagnostics: vec![],
+        };
+        let diagnostics = parse_steps(&steps, &FunctionIndex::default(), &decoder, true)
+            .unwrap()
+            .diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        // as reported by --strict
+        let vault = "Vault (0x1111111111111111111111111111111111111111)";
+        assert_eq!(
+            diagnostics,
+            vec![
+                format!("trace starts 5 gas into the call at pc 0 in {vault}"),
+                format!("unmatched jump out at pc 1 in {vault}"),
+                format!("unmatched return at pc 2 in {vault}"),
+                "unknown contract at pc 3 in 0x2222222222222222222222222222222222222222"
+                    .to_string(),
+            ]
+        );
+    }
+}
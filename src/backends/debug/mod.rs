@@ -1,5 +1,62 @@
+use super::{BackendProfile, ProfileBackend, ProfileInput};
+use crate::tree::CallTree;
+use async_trait::async_trait;
+use forge::traces::TraceKind;
+
+pub mod ast;
 pub mod debugger;
-mod function_call;
+pub mod diagnostic;
 mod parser;
 pub mod step;
 mod utils;
+
+pub use parser::DebugProfile;
+
+/// Builds the call tree, including internal functions, by stepping through the debug trace of
+/// the test.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DebugBackend;
+
+#[async_trait]
+impl ProfileBackend for DebugBackend {
+    fn name(&self) -> &str {
+        "debug"
+    }
+
+    fn description(&self) -> &str {
+        "internal functions and opcodes, from the debug trace"
+    }
+
+    fn needs_debug_steps(&self) -> bool {
+        true
+    }
+
+    async fn profile(&self, input: &ProfileInput<'_>) -> eyre::Result<BackendProfile> {
+        // the debugger only records the steps of the test itself
+        eyre::ensure!(
+            input.trace.is_none() && input.trace_kind == TraceKind::Execution,
+            "no debug steps were recorded for this trace of {}, only for the test itself",
+            input.test_name
+        );
+
+        let sources = input
+            .sources
+            .ok_or_else(|| eyre::eyre!("no sources for {}", input.test_name))?;
+        let profile = CallTree::from_debug_trace(
+            sources.clone(),
+            input.functions,
+            input.test_result,
+            input.decoder,
+            input.merge_stacks,
+        )?;
+        Ok(BackendProfile {
+            tree: profile.tree,
+            ordered: true,
+            diagnostics: profile
+                .diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        })
+    }
+}
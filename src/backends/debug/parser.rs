@@ -1,22 +1,46 @@
-use super::{debugger::Debugger, function_call::FunctionCall, step::VecStep, utils::get_next};
-use crate::flamegraph::{self, Flamegraph};
+use super::{
+    ast::FunctionIndex,
+    debugger::Debugger,
+    diagnostic::{Diagnostic, DiagnosticKind},
+    step::{Step, VecStep},
+    utils::get_next,
+};
+use crate::{
+    profile::{FrameKind, LineIndex},
+    tree::{CallTree, Frame},
+};
+use alloy_primitives::{Address, Selector};
 use forge::result::TestResult;
-use foundry_common::compile::ContractSources;
+use foundry_common::{compile::ContractSources, get_contract_name};
 use foundry_compilers::sourcemap::Jump;
 use foundry_evm_traces::CallTraceDecoder;
 use revm::interpreter::OpCode;
-use std::{
-    cell::RefCell,
-    rc::{Rc, Weak},
-};
 
-impl<'a> Flamegraph<'a> {
+/// Call tree of a test built by the debug backend.
+pub struct DebugProfile {
+    pub tree: CallTree,
+    /// What went wrong while building the call tree, the tree may be partial if not empty.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl CallTree {
+    /// Builds the call tree, including internal functions, by stepping through the debug trace.
+    ///
+    /// Internal functions are named after their definitions in `functions`.
     pub fn from_debug_trace(
         sources: ContractSources,
+        functions: &FunctionIndex,
         test_result: &TestResult,
         decoder: &CallTraceDecoder,
         merge_stacks: bool,
-    ) -> eyre::Result<Self> {
+    ) -> eyre::Result<DebugProfile> {
+        let lines = LineIndex::new(
+            sources
+                .sources_by_id
+                .iter()
+                .map(|(id, source)| (*id, source.as_str())),
+        );
+
         let builder = Debugger::builder()
             .debug_arenas(test_result.debug.as_slice())
             .sources(sources)
@@ -29,264 +53,834 @@ impl<'a> Flamegraph<'a> {
         debugger.try_run(&mut steps)?;
 
         // parse the debug steps into a call tree
-        let top_call = parse_steps(&steps, merge_stacks);
+        let ParsedSteps {
+            mut tree,
+            mut diagnostics,
+        } = parse_steps(&steps, functions, decoder, merge_stacks)?;
 
-        // parse the call tree into folded stack lines
-        let mut flamegraph = Self {
-            folded_stack_lines: vec![],
-            options: flamegraph::Options::default(),
-        };
-        flamegraph.handle_call(&top_call, None);
-        flamegraph.folded_stack_lines.reverse();
-        Ok(flamegraph)
+        tree.resolve_lines(&lines);
+        for source in diagnostics.iter_mut().filter_map(|d| d.source.as_mut()) {
+            source.line = source
+                .index
+                .and_then(|index| lines.line(index, source.offset));
+        }
+        Ok(DebugProfile { tree, diagnostics })
     }
+}
 
-    fn handle_call(
-        &mut self,
-        call: &Rc<RefCell<FunctionCall>>,
-        folded_stack_line_prepend: Option<&String>,
-    ) -> i64 {
-        let name = &call.borrow().name;
-        let children = &call.borrow().calls;
-
-        let folded_stack_line = folded_stack_line_prepend
-            .map(|prepend| format!("{};{}", prepend, name))
-            .unwrap_or_else(|| name.clone());
-
-        // the folded_stack_line is still incomplete, need to include gas
-        let idx = self.folded_stack_lines.len();
-        self.folded_stack_lines.push(folded_stack_line.clone());
-
-        let mut child_gas = 0;
-        for child in children {
-            child_gas += self.handle_call(child, Some(&folded_stack_line));
+/// Names the frame of an external call or deployment after its kind, e.g. `Vault.deposit(uint256)`
+/// for calls, `Proxy→Impl.upgradeTo(address)` for delegatecalls and `new Token` for deployments.
+fn frame_name(
+    decoder: &CallTraceDecoder,
+    kind: FrameKind,
+    caller: Address,
+    callee: Address,
+    contract_name: Option<String>,
+    input: &[u8],
+) -> String {
+    match kind {
+        FrameKind::Create | FrameKind::Create2 => {
+            let contract_name = contract_label(decoder, callee)
+                .or(contract_name)
+                .unwrap_or_else(|| callee.to_string());
+            format!("new {contract_name}")
         }
+        // the code of the callee runs in the context of the caller
+        FrameKind::DelegateCall | FrameKind::CallCode => format!(
+            "{}→{}",
+            contract_label(decoder, caller).unwrap_or_else(|| caller.to_string()),
+            external_call_name(decoder, callee, contract_name, input)
+        ),
+        _ => external_call_name(decoder, callee, contract_name, input),
+    }
+}
+
+/// Returns the name of the contract identified at `address`.
+fn contract_label(decoder: &CallTraceDecoder, address: Address) -> Option<String> {
+    decoder
+        .contracts
+        .get(&address)
+        .map(|identifier| get_contract_name(identifier).to_string())
+}
+
+/// Names an external call `Contract.function(types)` after the selector of its input, the same
+/// as the call trace backend does.
+///
+/// `receive()` and `fallback()` are only used when they are what ran, i.e. for an empty input or
+/// one without a selector. Unknown selectors are shown in hex.
+fn external_call_name(
+    decoder: &CallTraceDecoder,
+    address: Address,
+    contract_name: Option<String>,
+    input: &[u8],
+) -> String {
+    let contract_name = contract_label(decoder, address)
+        .or(contract_name)
+        .unwrap_or_else(|| "<unknown-contract>".to_string());
+
+    let function = if input.is_empty() && decoder.receive_contracts.contains(&address) {
+        "receive()".to_string()
+    } else if input.len() < 4 {
+        "fallback()".to_string()
+    } else {
+        let selector = Selector::from_slice(&input[..4]);
+        decoder
+            .functions
+            .get(&selector)
+            .and_then(|functions| functions.first())
+            .map(|function| function.signature())
+            .unwrap_or_else(|| selector.to_string())
+    };
 
-        let gas_start = call.borrow().gas_start;
-        let gas_end = call.borrow().gas_end;
-        let gas_used = gas_end
-            .map(|gas_end| (gas_end as i64) - (gas_start as i64))
-            .unwrap_or(0);
-        let mut gas_here = gas_used - child_gas;
-        if gas_here < 0 {
-            // because some issues with flamegraph
-            gas_here = 0;
+    format!("{contract_name}.{function}")
+}
+
+/// Returns the input of the call made by a CALL, CALLCODE, DELEGATECALL or STATICCALL step, as
+/// read from the memory of the caller.
+fn call_input(step: &Step) -> Option<&[u8]> {
+    // CALL and CALLCODE take a value before the input
+    let args = match step.current_step.instruction {
+        0xF1 | 0xF2 => 3,
+        0xF4 | 0xFA => 2,
+        _ => return None,
+    };
+    let mut stack = step.current_step.stack.iter().rev().skip(args);
+    let offset = usize::try_from(*stack.next()?).ok()?;
+    let length = usize::try_from(*stack.next()?).ok()?;
+    if length == 0 {
+        return Some(&[]);
+    }
+    step.current_step
+        .memory
+        .get(offset..offset.checked_add(length)?)
+}
+
+/// Returns the decoded revert reason if the step is a REVERT or INVALID, whose call reverts.
+fn revert_reason(decoder: &CallTraceDecoder, step: &Step) -> Option<String> {
+    match step.current_step.instruction {
+        0xFD => {
+            let data = revert_data(step).unwrap_or_default();
+            Some(decoder.revert_decoder.decode(data, None))
         }
+        0xFE => Some("invalid opcode".to_string()),
+        _ => None,
+    }
+}
 
-        // adding the gas in the folded_stack_line
-        self.folded_stack_lines[idx] = format!("{} {}", self.folded_stack_lines[idx], gas_here);
+/// Returns the data of a REVERT step, as read from memory at the offset and of the length on top
+/// of the stack.
+fn revert_data(step: &Step) -> Option<&[u8]> {
+    let mut stack = step.current_step.stack.iter().rev();
+    let offset = usize::try_from(*stack.next()?).ok()?;
+    let length = usize::try_from(*stack.next()?).ok()?;
+    if length == 0 {
+        return Some(&[]);
+    }
+    step.current_step
+        .memory
+        .get(offset..offset.checked_add(length)?)
+}
+
+/// Returns the gas used by the step, if the next step runs in the same call.
+///
+/// The gas of calls and deployments is not included, it is accounted to the callee.
+fn step_gas(step: &Step, next: Option<&Step>) -> u64 {
+    let Some(next) = next else { return 0 };
+    let is_call = matches!(
+        step.current_step.instruction,
+        0xF0 | 0xF1 | 0xF2 | 0xF4 | 0xF5 | 0xFA
+    );
+    if is_call || next.address != step.address {
+        return 0;
+    }
+    next.current_step
+        .total_gas_used
+        .saturating_sub(step.current_step.total_gas_used)
+}
+
+/// Call tree built from the debug steps, along with what went wrong while building it.
+pub struct ParsedSteps {
+    pub tree: CallTree,
+    pub diagnostics: Vec<Diagnostic>,
+}
 
-        gas_used
+fn diagnostic(decoder: &CallTraceDecoder, kind: DiagnosticKind, step: &Step) -> Diagnostic {
+    Diagnostic {
+        kind,
+        address: step.address,
+        contract: contract_label(decoder, step.address),
+        pc: step.current_step.pc,
+        source: step.source_location(),
     }
 }
 
-pub fn parse_steps(steps: &VecStep, merge_stacks: bool) -> Rc<RefCell<FunctionCall>> {
-    let steps = &steps.0;
-    assert_eq!(
-        steps[0].current_step.total_gas_used, 0,
-        "this should be the start"
+pub fn parse_steps(
+    steps: &VecStep,
+    functions: &FunctionIndex,
+    decoder: &CallTraceDecoder,
+    merge_stacks: bool,
+) -> eyre::Result<ParsedSteps> {
+    let mut diagnostics = steps.diagnostics.clone();
+    let steps = &steps.steps;
+    let Some(first) = steps.first() else {
+        eyre::bail!("no debug step could be mapped to the sources");
+    };
+    if first.current_step.total_gas_used != 0 {
+        diagnostics.push(diagnostic(
+            decoder,
+            DiagnosticKind::UnexpectedStart {
+                gas: first.current_step.total_gas_used,
+            },
+            first,
+        ));
+    }
+
+    let name = frame_name(
+        decoder,
+        first.call_kind.into(),
+        Address::ZERO,
+        first.address,
+        first.get_contract_name(),
+        &first.current_step.calldata,
     );
-    let contract_name = steps[0]
-        .get_contract_name()
-        .expect("source code should be of contract");
-    let top_call = Rc::new(RefCell::new(FunctionCall {
-        title: format!("{contract_name}.fallback"),
-        name: format!("{contract_name}.fallback"),
-        gas_start: 0,
-        gas_end: None,
-        color: String::new(),
-        is_external_call: true,
-        calls: vec![],
-        parent: None,
-    }));
-    let mut ptr = Rc::clone(&top_call);
+    let mut tree = CallTree::new(Frame {
+        address: Some(first.address),
+        source: first.source_location(),
+        instructions: Some(0),
+        ..Frame::new(
+            name,
+            first.call_kind.into(),
+            first.current_step.total_gas_used,
+        )
+    });
+    // the frame the steps are executed in
+    let mut ptr = tree.root();
 
     for (i, step) in steps.iter().enumerate() {
+        let step_next = steps.get(i + 1);
+
+        // the step is executed by the current frame, unless it gets an opcode frame below
+        if merge_stacks || i == 0 {
+            let frame = tree.frame_mut(ptr);
+            *frame.instructions.get_or_insert(0) += 1;
+            frame.add_cost(step.source_location(), step_gas(step, step_next));
+        }
+
         if i == 0 {
             // we have handled the first one already
             continue;
         }
 
         if step.source_element.jump == Jump::In {
-            let step_next = &steps[i + 1];
-            let function_name = step.get_name(); //.expect("source code should be of function");
-            let function_name_next = step_next.get_function_name(); //.expect("source code should be of function");
-            if function_name.is_none() && function_name_next.is_none() {
-                continue;
+            let Some(step_next) = step_next else {
+                diagnostics.push(diagnostic(decoder, DiagnosticKind::TruncatedTrace, step));
+                break;
+            };
+            // the jump lands on the definition of the function, the source snippets are only
+            // guessed from when it is not in the ast, e.g. for compiler generated code
+            let index = step_next.source_element.index;
+            if let Some(index) = index.filter(|index| functions.is_ambiguous(*index)) {
+                let kind = DiagnosticKind::AmbiguousSource { index };
+                // once per source
+                if !diagnostics.iter().any(|diagnostic| diagnostic.kind == kind) {
+                    diagnostics.push(diagnostic(decoder, kind, step_next));
+                }
+            }
+            let function_name = index
+                .and_then(|index| functions.find(index, step_next.source_element.offset))
+                .map(str::to_string)
+                .or_else(|| step.get_name())
+                .or_else(|| step_next.get_function_name());
+            if let Some(function_name) = function_name {
+                ptr = tree.push(
+                    ptr,
+                    Frame {
+                        address: Some(step.address),
+                        source: step_next.source_location(),
+                        instructions: Some(0),
+                        ..Frame::new(
+                            function_name,
+                            FrameKind::Internal,
+                            step.current_step.total_gas_used,
+                        )
+                    },
+                );
             }
-            // if function_name != function_name_next {
-            //     // panic!("function name mismatch {} {}", function_name, function_name_next);
-            //     continue;
-            // }
-            let function_name = function_name.or(function_name_next).unwrap();
-
-            let ptr_weak = Rc::downgrade(&ptr);
-            let new_call = Rc::new(RefCell::new(FunctionCall {
-                title: format!("{function_name} internal jump"),
-                name: function_name,
-                gas_start: step.current_step.total_gas_used,
-                gas_end: None,
-                color: String::new(),
-                is_external_call: false,
-                calls: vec![],
-                parent: Some(ptr_weak),
-            }));
-            ptr.borrow_mut().calls.push(Rc::clone(&new_call));
-            ptr = new_call;
         }
 
         // if stacks are merged, some ops like DUP1 get shown
         if !merge_stacks {
-            let ptr_weak = Rc::downgrade(&ptr);
-            let step_next = &steps.get(i + 1);
             let opcode = OpCode::new(step.current_step.instruction)
-                .unwrap()
-                .to_string();
-            let new_call = Rc::new(RefCell::new(FunctionCall {
-                title: opcode.clone(),
-                name: opcode,
-                gas_start: step.current_step.total_gas_used,
-                gas_end: step_next.map(|step_next| step_next.current_step.total_gas_used),
-                color: String::new(),
-                is_external_call: false,
-                calls: vec![],
-                parent: Some(ptr_weak),
-            }));
-            ptr.borrow_mut().calls.push(Rc::clone(&new_call));
+                .map(|opcode| opcode.to_string())
+                .unwrap_or_else(|| format!("0x{:02x}", step.current_step.instruction));
+            let gas_start = step.current_step.total_gas_used;
+            tree.push(
+                ptr,
+                Frame {
+                    gas_end: step_next
+                        .map(|step_next| step_next.current_step.total_gas_used)
+                        .unwrap_or(gas_start),
+                    address: Some(step.address),
+                    source: step.source_location(),
+                    instructions: Some(1),
+                    ..Frame::new(opcode, FrameKind::Opcode, gas_start)
+                },
+            );
         }
 
-        // CALL or STATICCALL
-        if step.current_step.instruction == 0xF1 || step.current_step.instruction == 0xFA {
-            let ptr_weak = Rc::downgrade(&ptr);
-            let step_next = &steps[i + 1];
+        // external calls and deployments
+        let call_kind = match step.current_step.instruction {
+            0xF0 => Some(FrameKind::Create),
+            0xF1 => Some(FrameKind::Call),
+            0xF2 => Some(FrameKind::CallCode),
+            0xF4 => Some(FrameKind::DelegateCall),
+            0xF5 => Some(FrameKind::Create2),
+            0xFA => Some(FrameKind::StaticCall),
+            _ => None,
+        };
+        if let Some(call_kind) = call_kind {
+            let Some(step_next) = step_next else {
+                diagnostics.push(diagnostic(decoder, DiagnosticKind::TruncatedTrace, step));
+                break;
+            };
+            let is_create = matches!(call_kind, FrameKind::Create | FrameKind::Create2);
             if let Some(contract_name) = step_next.get_contract_name() {
-                let new_call = Rc::new(RefCell::new(FunctionCall {
-                    title: format!("{contract_name}.fallback"),
-                    name: format!("{contract_name}.fallback"),
-                    gas_start: step.current_step.total_gas_used,
-                    gas_end: None,
-                    color: String::new(),
-                    is_external_call: true,
-                    calls: vec![],
-                    parent: Some(ptr_weak),
-                }));
-                ptr.borrow_mut().calls.push(Rc::clone(&new_call));
-                ptr = new_call;
+                let name = frame_name(
+                    decoder,
+                    call_kind,
+                    step.address,
+                    step_next.address,
+                    Some(contract_name),
+                    &step_next.current_step.calldata,
+                );
+                ptr = tree.push(
+                    ptr,
+                    Frame {
+                        address: Some(step_next.address),
+                        source: step_next.source_location(),
+                        instructions: Some(0),
+                        ..Frame::new(name, call_kind, step.current_step.total_gas_used)
+                    },
+                );
             } else {
-                let function_name = get_next(&step.source_code, "", vec!['(']);
-                let function_name_next = step_next.get_name();
-                let function_name = function_name.or(function_name_next);
-                if function_name.is_none() {
-                    println!(
-                        "no-name for native function at {:#?} \nnext step {:#?}",
-                        step, step_next
-                    );
-                    break;
+                // the callee has no source, so the next step is back in the caller. the address
+                // of a call is on the stack, the address of a deployment is pushed once it is done
+                let address = if is_create {
+                    step_next.current_step.stack.last()
+                } else {
+                    step.current_step.stack.iter().rev().nth(1)
                 }
-                let function_name = function_name.unwrap();
-                let new_call = Rc::new(RefCell::new(FunctionCall {
-                    title: format!("{function_name} nativecode"),
-                    name: function_name,
-                    gas_start: step.current_step.total_gas_used,
-                    gas_end: Some(step_next.current_step.total_gas_used),
-                    color: String::new(),
-                    is_external_call: true,
-                    calls: vec![],
-                    parent: Some(ptr_weak),
-                }));
-                ptr.borrow_mut().calls.push(Rc::clone(&new_call));
+                .map(|address| Address::from_word((*address).into()))
+                .unwrap_or_default();
+                let input = call_input(step).unwrap_or_default();
+                // decode the input if the callee is known, e.g. cheatcodes, otherwise guess from
+                // the source of the call
+                let function_name = if is_create || decoder.contracts.contains_key(&address) {
+                    frame_name(decoder, call_kind, step.address, address, None, input)
+                } else {
+                    get_next(&step.source_code, "", vec!['('])
+                        .or_else(|| step_next.get_name())
+                        .unwrap_or_else(|| {
+                            diagnostics.push(Diagnostic {
+                                address,
+                                contract: None,
+                                ..diagnostic(decoder, DiagnosticKind::UnknownContract, step)
+                            });
+                            frame_name(decoder, call_kind, step.address, address, None, input)
+                        })
+                };
+                tree.push(
+                    ptr,
+                    Frame {
+                        gas_end: step_next.current_step.total_gas_used,
+                        address: Some(address),
+                        source: step.source_location(),
+                        instructions: Some(0),
+                        ..Frame::new(function_name, call_kind, step.current_step.total_gas_used)
+                    },
+                );
             };
         }
 
         // internal function call ends
         if step.source_element.jump == Jump::Out {
-            // let name = step.get_name().unwrap_or("unknown".to_string());
-            // let step_next = &steps[i + 1];
-            // if !step_next.source_code.contains(&name) {
-            //     continue;
-            // }
-
-            // let ptr_weak = Rc::downgrade(&ptr);
-            // let return_dummy_call = Rc::new(RefCell::new(FunctionCall {
-            //     title: format!(
-            //         "return {name} pc: {}, total_gas_used: {}",
-            //         step.current_step.pc, step.current_step.total_gas_used
-            //     ),
-            //     name: "return".to_string(),
-            //     gas_start: 0,
-            //     gas_end: Some(0),
-            //     is_external_call: false,
-            //     color: String::new(),
-            //     calls: vec![],
-            //     parent: Some(ptr_weak),
-            // }));
-            // ptr.borrow_mut().calls.push(return_dummy_call);
-            let parent_ptr = if let Some(ptr) = ptr.borrow_mut().parent.as_ref() {
-                Weak::clone(ptr)
+            // external calls only end by halting
+            let parent = if tree.node(ptr).frame.kind.is_external() {
+                None
             } else {
-                println!("no parent found for {}", step.source_code);
-                break;
+                tree.parent(ptr)
             };
+            match parent {
+                Some(parent) => {
+                    tree.frame_mut(ptr).gas_end = step.current_step.total_gas_used;
+                    ptr = parent;
+                }
+                None => {
+                    diagnostics.push(diagnostic(decoder, DiagnosticKind::UnmatchedJumpOut, step))
+                }
+            }
+        }
 
-            ptr.borrow_mut().gas_end = Some(step.current_step.total_gas_used);
+        // STOP, RETURN, REVERT, INVALID or SELFDESTRUCT
+        if matches!(
+            step.current_step.instruction,
+            0x00 | 0xF3 | 0xFD | 0xFE | 0xFF
+        ) {
+            let revert = revert_reason(decoder, step);
+            // the call ends, along with the internal functions it was in
+            loop {
+                let is_external = tree.node(ptr).frame.kind.is_external();
+                let frame = tree.frame_mut(ptr);
+                frame.gas_end = step.current_step.total_gas_used;
+                if revert.is_some() {
+                    frame.revert.clone_from(&revert);
+                }
+                let Some(parent) = tree.parent(ptr) else {
+                    // only the last step may end the top call
+                    if step_next.is_some() {
+                        diagnostics.push(diagnostic(
+                            decoder,
+                            DiagnosticKind::UnmatchedReturn,
+                            step,
+                        ));
+                    }
+                    break;
+                };
+                ptr = parent;
+                if is_external {
+                    break;
+                }
+            }
+        }
+    }
 
-            ptr = parent_ptr.upgrade().unwrap();
+    // the calls still open at the end of the trace end with its last step
+    let last = &steps[steps.len() - 1];
+    if ptr != tree.root() {
+        diagnostics.push(diagnostic(decoder, DiagnosticKind::TruncatedTrace, last));
+    }
+    for id in tree.path_to_root(ptr) {
+        let frame = tree.frame_mut(id);
+        if frame.gas_end == frame.gas_start {
+            frame.gas_end = last.current_step.total_gas_used;
         }
+    }
 
-        if step.current_step.instruction == 0xF3
-            || step.current_step.instruction == 0xFD
-            || step.current_step.instruction == 0x00
-        {
-            let parent_ptr = if let Some(ptr) = ptr.borrow_mut().parent.as_ref() {
-                Weak::clone(ptr)
-            } else {
-                println!("no parent found for {}", step.source_code);
-                break;
-            };
+    Ok(ParsedSteps { tree, diagnostics })
+}
 
-            ptr.borrow_mut().gas_end = Some(step.current_step.total_gas_used);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloy_primitives::U256;
+    use foundry_evm_traces::CallTraceDecoderBuilder;
+    use revm_inspectors::tracing::types::CallKind;
 
-            ptr = parent_ptr.upgrade().unwrap();
-        }
+    fn jump(mut step: Step, jump: Jump) -> Step {
+        step.source_element.jump = jump;
+        step
+    }
+
+    fn parse(
+        steps: Vec<Step>,
+        functions: &FunctionIndex,
+        decoder: &CallTraceDecoder,
+    ) -> ParsedSteps {
+        let steps = VecStep {
+            steps,
+            diagnostics: vec![],
+        };
+        parse_steps(&steps, functions, decoder, true).unwrap()
+    }
+
+    fn frames(tree: &CallTree) -> Vec<(&str, u64, u64)> {
+        tree.nodes()
+            .iter()
+            .map(|node| {
+                let frame = &node.frame;
+                (frame.name.as_str(), frame.gas_start, frame.gas_end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_nested_internal_functions() {
+        let vault = Address::repeat_byte(0x11);
+        let mut functions = FunctionIndex::default();
+        functions.define(0, 100, 200, "Vault.deposit(uint256)");
+
+        let mut first = Step::mock(vault, "contract Vault {", 0x5B, 0);
+        first.current_step.calldata = vec![0xde, 0xad, 0xbe, 0xef].into();
+        let mut deposit = Step::mock(vault, "function deposit(uint256 amount) {", 0x5B, 18);
+        deposit.source_element.offset = 100;
+        let steps = vec![
+            first,
+            jump(Step::mock(vault, "deposit(1)", 0x56, 10), Jump::In),
+            deposit,
+            jump(Step::mock(vault, "_mint(amount)", 0x56, 20), Jump::In),
+            // not in the ast, named after the source of the jump
+            Step::mock(vault, "function _mint(uint256 amount) internal {", 0x5B, 28),
+            jump(Step::mock(vault, "}", 0x56, 40), Jump::Out),
+            jump(Step::mock(vault, "}", 0x56, 50), Jump::Out),
+            Step::mock(vault, "contract Vault {", 0x00, 55),
+        ];
+        let ParsedSteps { tree, diagnostics } =
+            parse(steps, &functions, &CallTraceDecoderBuilder::new().build());
+
+        assert_eq!(
+            frames(&tree),
+            vec![
+                ("Vault.0xdeadbeef", 0, 55),
+                ("Vault.deposit(uint256)", 10, 50),
+                ("_mint", 20, 40),
+            ]
+        );
+        assert_eq!(tree.nodes()[2].parent, Some(tree.nodes()[1].id));
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_unmatched_jump_out() {
+        let vault = Address::repeat_byte(0x11);
+        let steps = vec![
+            Step::mock(vault, "contract Vault {", 0x5B, 0),
+            jump(Step::mock(vault, "}", 0x56, 5), Jump::Out),
+            Step::mock(vault, "contract Vault {", 0x00, 9),
+        ];
+        let ParsedSteps { tree, diagnostics } = parse(
+            steps,
+            &FunctionIndex::default(),
+            &CallTraceDecoderBuilder::new().build(),
+        );
+
+        // the jump can't leave the call
+        assert_eq!(frames(&tree), vec![("Vault.fallback()", 0, 9)]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::UnmatchedJumpOut);
+    }
+
+    #[test]
+    fn test_delegatecall_and_create_names() {
+        let proxy = Address::repeat_byte(0x11);
+        let implementation = Address::repeat_byte(0x22);
+        let token = Address::repeat_byte(0x33);
+        let mut decoder = CallTraceDecoderBuilder::new().build();
+        decoder
+            .contracts
+            .insert(proxy, "src/Proxy.sol:Proxy".to_string());
+
+        let mut delegated = Step::mock(implementation, "contract Impl {", 0x5B, 12);
+        delegated.call_kind = CallKind::DelegateCall;
+        delegated.current_step.calldata = vec![0x12, 0x34, 0x56, 0x78].into();
+        let mut deployed = Step::mock(token, "contract Token {", 0x5B, 34);
+        deployed.call_kind = CallKind::Create;
+        let steps = vec![
+            Step::mock(proxy, "contract Proxy {", 0x5B, 0),
+            Step::mock(proxy, "_delegate(implementation)", 0xF4, 10),
+            delegated,
+            Step::mock(implementation, "contract Impl {", 0x00, 30),
+            Step::mock(proxy, "new Token()", 0xF0, 32),
+            deployed,
+            Step::mock(token, "contract Token {", 0xF3, 60),
+            Step::mock(proxy, "contract Proxy {", 0x00, 62),
+        ];
+        let ParsedSteps { tree, diagnostics } = parse(steps, &FunctionIndex::default(), &decoder);
+
+        assert_eq!(
+            frames(&tree),
+            vec![
+                ("Proxy.fallback()", 0, 62),
+                ("Proxy→Impl.0x12345678", 10, 30),
+                ("new Token", 32, 60),
+            ]
+        );
+        let kinds = tree
+            .nodes()
+            .iter()
+            .map(|node| node.frame.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![FrameKind::Call, FrameKind::DelegateCall, FrameKind::Create]
+        );
+        assert_eq!(tree.nodes()[2].parent, Some(tree.root()));
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_trace() {
+        let vault = Address::repeat_byte(0x11);
+        let mut functions = FunctionIndex::default();
+        functions.define(0, 0, 100, "Vault.deposit(uint256)");
+        let decoder = CallTraceDecoderBuilder::new().build();
 
-        // // external call ends
-        // if step.current_step.instruction == 0xF3
-        //     || step.current_step.instruction == 0xFD
-        //     || step.current_step.instruction == 0x00
-        // {
-        //     loop {
-        //         let parent_ptr = Weak::clone(ptr.borrow_mut().parent.as_ref().unwrap());
-
-        //         let step_next = &steps[i + 1];
-        //         ptr.borrow_mut().gas_end = Some(step_next.current_step.total_gas_used);
-
-        //         // let prev_ptr = ptr;
-        //         ptr = parent_ptr.upgrade().unwrap();
-
-        //         // if prev_ptr.borrow_mut().is_external_call {
-        //         //     let ptr_weak = Rc::downgrade(&ptr);
-        //         //     let name = step
-        //         //         .get_name()
-        //         //         .unwrap_or_else(|| step.get_source_code_stripped(30));
-        //         //     let return_dummy_call = Rc::new(RefCell::new(FunctionCall {
-        //         //         title: format!(
-        //         //             "returncall {name} pc: {}, total_gas_used: {}",
-        //         //             step.current_step.pc, step.current_step.total_gas_used
-        //         //         ),
-        //         //         name: "return".to_string(),
-        //         //         gas_start: 0,
-        //         //         gas_end: Some(0),
-        //         //         is_external_call: false,
-        //         //         color: String::new(),
-        //         //         calls: vec![],
-        //         //         parent: Some(ptr_weak),
-        //         //     }));
-        //         //     ptr.borrow_mut().calls.push(return_dummy_call);
-        //         //     break;
-        //         // }
-        //     }
-        // }
+        // the trace ends inside a function, which ends with the last step
+        let steps = vec![
+            Step::mock(vault, "contract Vault {", 0x5B, 0),
+            jump(Step::mock(vault, "deposit(1)", 0x56, 10), Jump::In),
+            Step::mock(vault, "function deposit(uint256 amount) {", 0x5B, 18),
+        ];
+        let ParsedSteps { tree, diagnostics } = parse(steps, &functions, &decoder);
+        assert_eq!(
+            frames(&tree),
+            vec![
+                ("Vault.fallback()", 0, 18),
+                ("Vault.deposit(uint256)", 10, 18)
+            ]
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::TruncatedTrace);
+
+        // the trace ends on the jump, the function it jumps into is unknown
+        let steps = vec![
+            Step::mock(vault, "contract Vault {", 0x5B, 0),
+            jump(Step::mock(vault, "deposit(1)", 0x56, 10), Jump::In),
+        ];
+        let ParsedSteps { tree, diagnostics } = parse(steps, &functions, &decoder);
+        assert_eq!(frames(&tree), vec![("Vault.fallback()", 0, 10)]);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::TruncatedTrace);
     }
 
-    top_call
+    /// Returns a call step at `address` with `stack`, top last, and `memory`.
+    fn call(address: Address, instruction: u8, stack: &[U256], memory: &[u8]) -> Step {
+        let mut step = Step::mock(address, "token.mint(amount)", instruction, 10);
+        step.current_step.stack = stack.to_vec();
+        step.current_step.memory = memory.to_vec().into();
+        step
+    }
+
+    fn word(address: Address) -> U256 {
+        U256::from_be_slice(address.as_slice())
+    }
+
+    #[test]
+    fn test_call_input() {
+        let vault = Address::repeat_byte(0x11);
+        let token = word(Address::repeat_byte(0x22));
+        let gas = U256::from(50_000);
+        let mut memory = vec![0; 32];
+        memory.extend([0xde, 0xad, 0xbe, 0xef]);
+        memory.extend([0; 32]);
+        let words = |values: &[u64]| values.iter().map(|v| U256::from(*v)).collect::<Vec<_>>();
+
+        // retLength, retOffset, argsLength, argsOffset, value, address, gas
+        let mut stack = words(&[0, 0, 36, 32, 0]);
+        stack.extend([token, gas]);
+        let step = call(vault, 0xF1, &stack, &memory);
+        assert_eq!(call_input(&step), Some(&memory[32..68]));
+
+        // retLength, retOffset, argsLength, argsOffset, address, gas
+        let mut stack = words(&[0, 0, 4, 32]);
+        stack.extend([token, gas]);
+        let step = call(vault, 0xFA, &stack, &memory);
+        assert_eq!(call_input(&step), Some(&[0xde, 0xad, 0xbe, 0xef][..]));
+
+        // an empty input may be anywhere
+        let mut stack = words(&[0, 0, 0, 1 << 40, 0]);
+        stack.extend([token, gas]);
+        let step = call(vault, 0xF2, &stack, &[]);
+        assert_eq!(call_input(&step), Some(&[][..]));
+
+        // past the end of the memory
+        let mut stack = words(&[0, 0, 64, 32]);
+        stack.extend([token, gas]);
+        let step = call(vault, 0xF4, &stack, &memory);
+        assert_eq!(call_input(&step), None);
+
+        // not enough stack, or not a call
+        let step = call(vault, 0xF1, &[token, gas], &memory);
+        assert_eq!(call_input(&step), None);
+        let step = call(vault, 0x01, &words(&[1, 2]), &memory);
+        assert_eq!(call_input(&step), None);
+    }
+
+    #[test]
+    fn test_external_call_name() {
+        let vault = Address::repeat_byte(0x11);
+        let mut decoder = CallTraceDecoderBuilder::new().build();
+        decoder
+            .contracts
+            .insert(vault, "src/Vault.sol:Vault".to_string());
+        let name = |decoder: &CallTraceDecoder, input: &[u8]| {
+            external_call_name(decoder, vault, Some("Source".to_string()), input)
+        };
+
+        // the identified contract is preferred to the source
+        assert_eq!(name(&decoder, &[]), "Vault.fallback()");
+        assert_eq!(name(&decoder, &[0xde, 0xad]), "Vault.fallback()");
+        assert_eq!(
+            name(&decoder, &[0xde, 0xad, 0xbe, 0xef, 0x01]),
+            "Vault.0xdeadbeef"
+        );
+        decoder.receive_contracts.extend([vault]);
+        assert_eq!(name(&decoder, &[]), "Vault.receive()");
+        // only an empty input runs receive
+        assert_eq!(name(&decoder, &[0xde]), "Vault.fallback()");
+
+        let token = Address::repeat_byte(0x22);
+        assert_eq!(
+            external_call_name(&decoder, token, Some("Token".to_string()), &[]),
+            "Token.fallback()"
+        );
+        assert_eq!(
+            external_call_name(&decoder, token, None, &[0x12, 0x34, 0x56, 0x78]),
+            "<unknown-contract>.0x12345678"
+        );
+    }
+
+    #[test]
+    fn test_calls_without_source() {
+        let vault = Address::repeat_byte(0x11);
+        let token = Address::repeat_byte(0x22);
+        let other = Address::repeat_byte(0x33);
+        let mut decoder = CallTraceDecoderBuilder::new().build();
+        decoder
+            .contracts
+            .insert(token, "src/Token.sol:Token".to_string());
+
+        let mut mint = call(
+            vault,
+            0xF1,
+            &[
+                U256::ZERO,
+                U256::ZERO,
+                U256::from(4),
+                U256::ZERO,
+                U256::ZERO,
+                word(token),
+            ],
+            &[0xde, 0xad, 0xbe, 0xef],
+        );
+        mint.current_step.stack.push(U256::from(50_000));
+        let mut burn = call(vault, 0xFA, &[U256::ZERO; 4], &[]);
+        burn.source_code = "other.burn(amount)".to_string();
+        burn.current_step
+            .stack
+            .extend([word(other), U256::from(50_000)]);
+        burn.current_step.total_gas_used = 45;
+        // the callees have no source, so the next steps are back in the vault
+        let steps = vec![
+            Step::mock(vault, "contract Vault {", 0x5B, 0),
+            mint,
+            Step::mock(vault, "token.mint(amount)", 0x15, 40),
+            burn,
+            Step::mock(vault, "other.burn(amount)", 0x15, 70),
+            Step::mock(vault, "contract Vault {", 0x00, 75),
+        ];
+        let ParsedSteps { tree, diagnostics } = parse(steps, &FunctionIndex::default(), &decoder);
+
+        // the input of a known contract is decoded, the other call is named after its source
+        assert_eq!(
+            frames(&tree),
+            vec![
+                ("Vault.fallback()", 0, 75),
+                ("Token.0xdeadbeef", 10, 40),
+                ("other.burn", 45, 70),
+            ]
+        );
+        assert_eq!(tree.nodes()[1].frame.address, Some(token));
+        assert_eq!(tree.nodes()[2].frame.address, Some(other));
+        assert!(diagnostics.is_empty());
+    }
+
+    /// Returns a REVERT step at `address` of `data`, stored in memory after a word.
+    fn revert(address: Address, data: &[u8], total_gas_used: u64) -> Step {
+        let mut step = Step::mock(
+            address,
+            "revert InsufficientBalance()",
+            0xFD,
+            total_gas_used,
+        );
+        let mut memory = vec![0; 32];
+        memory.extend_from_slice(data);
+        step.current_step.memory = memory.into();
+        // length, offset
+        step.current_step.stack = vec![U256::from(data.len()), U256::from(32)];
+        step
+    }
+
+    /// Returns the `Error(string)` revert data of `reason`.
+    fn error_string(reason: &str) -> Vec<u8> {
+        let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+        data.extend(U256::from(32).to_be_bytes::<32>());
+        data.extend(U256::from(reason.len()).to_be_bytes::<32>());
+        let mut padded = reason.as_bytes().to_vec();
+        padded.resize(reason.len().div_ceil(32) * 32, 0);
+        data.extend(padded);
+        data
+    }
+
+    #[test]
+    fn test_revert_data() {
+        let vault = Address::repeat_byte(0x11);
+        let step = revert(vault, &[0xde, 0xad, 0xbe, 0xef], 0);
+        assert_eq!(revert_data(&step), Some(&[0xde, 0xad, 0xbe, 0xef][..]));
+
+        let step = revert(vault, &[], 0);
+        assert_eq!(revert_data(&step), Some(&[][..]));
+
+        // past the end of the memory
+        let mut step = revert(vault, &[0xde, 0xad], 0);
+        step.current_step.stack[0] = U256::from(64);
+        assert_eq!(revert_data(&step), None);
+        step.current_step.stack.clear();
+        assert_eq!(revert_data(&step), None);
+    }
+
+    #[test]
+    fn test_revert_reason() {
+        let vault = Address::repeat_byte(0x11);
+        let decoder = CallTraceDecoderBuilder::new().build();
+
+        let step = revert(vault, &error_string("insufficient balance"), 0);
+        let reason = revert_reason(&decoder, &step).unwrap();
+        assert!(reason.contains("insufficient balance"), "{reason}");
+
+        // a custom error that is not in the abis of the project
+        let mut data = vec![0xde, 0xad, 0xbe, 0xef];
+        data.extend(U256::from(7).to_be_bytes::<32>());
+        let step = revert(vault, &data, 0);
+        let reason = revert_reason(&decoder, &step).unwrap();
+        assert!(reason.starts_with("custom error"), "{reason}");
+
+        let step = Step::mock(vault, "assert(false)", 0xFE, 0);
+        assert_eq!(
+            revert_reason(&decoder, &step).as_deref(),
+            Some("invalid opcode")
+        );
+        let step = Step::mock(vault, "contract Vault {", 0xF3, 0);
+        assert_eq!(revert_reason(&decoder, &step), None);
+    }
+
+    #[test]
+    fn test_revert_in_internal_function() {
+        let vault = Address::repeat_byte(0x11);
+        let mut functions = FunctionIndex::default();
+        functions.define(0, 100, 200, "Vault.withdraw(uint256)");
+        let decoder = CallTraceDecoderBuilder::new().build();
+
+        let mut withdraw = Step::mock(vault, "function withdraw(uint256 amount) {", 0x5B, 18);
+        withdraw.source_element.offset = 100;
+        let steps = vec![
+            Step::mock(vault, "contract Vault {", 0x5B, 0),
+            jump(Step::mock(vault, "withdraw(1)", 0x56, 10), Jump::In),
+            withdraw,
+            revert(vault, &error_string("insufficient balance"), 30),
+        ];
+        let ParsedSteps { tree, diagnostics } = parse(steps, &functions, &decoder);
+
+        // the call reverts along with the function it was in
+        assert_eq!(
+            frames(&tree),
+            vec![
+                ("Vault.fallback()", 0, 30),
+                ("Vault.withdraw(uint256)", 10, 30)
+            ]
+        );
+        for node in tree.nodes() {
+            let revert = node.frame.revert.as_deref().unwrap_or_default();
+            assert!(revert.contains("insufficient balance"), "{revert}");
+        }
+        assert!(diagnostics.is_empty());
+    }
 }
@@ -1,13 +1,23 @@
+use alloy_primitives::Address;
 use forge::debug::DebugStep;
 use foundry_compilers::sourcemap::SourceElement;
 use revm::interpreter::OpCode;
+use revm_inspectors::tracing::types::CallKind;
 
-use super::utils::{get_after_dot, get_next};
+use super::{
+    diagnostic::Diagnostic,
+    utils::{get_after_dot, get_next},
+};
+use crate::profile::SourceLocation;
 
 pub struct Step {
     pub source_element: SourceElement,
     pub source_code: String,
     pub current_step: DebugStep,
+    /// Address of the contract being executed.
+    pub address: Address,
+    /// Kind of the call being executed.
+    pub call_kind: CallKind,
 }
 
 impl std::fmt::Debug for Step {
@@ -59,6 +69,15 @@ impl Step {
             .or_else(|| get_next(&self.source_code, "", vec!['(']))
     }
 
+    pub fn source_location(&self) -> Option<SourceLocation> {
+        Some(SourceLocation {
+            index: self.source_element.index,
+            offset: self.source_element.offset,
+            length: self.source_element.length,
+            line: None,
+        })
+    }
+
     pub fn get_source_code_stripped(&self, len: usize) -> String {
         if self.source_code.len() > len {
             self.source_code[..len].to_string()
@@ -68,11 +87,58 @@ impl Step {
     }
 }
 
+#[cfg(test)]
+impl Step {
+    /// Returns a step of `instruction` in the contract at `address`, with `total_gas_used` gas
+    /// used before it, mapped to `source_code` at the start of the source 0.
+    pub(crate) fn mock(
+        address: Address,
+        source_code: &str,
+        instruction: u8,
+        total_gas_used: u64,
+    ) -> Self {
+        use foundry_compilers::sourcemap::Jump;
+
+        Self {
+            source_element: SourceElement {
+                offset: 0,
+                length: source_code.len(),
+                index: Some(0),
+                jump: Jump::Regular,
+                modifier_depth: 0,
+            },
+            source_code: source_code.to_string(),
+            current_step: DebugStep {
+                instruction,
+                total_gas_used,
+                ..Default::default()
+            },
+            address,
+            call_kind: CallKind::Call,
+        }
+    }
+}
+
 #[derive(Default, Debug)]
-pub struct VecStep(pub Vec<Step>);
+pub struct VecStep {
+    pub steps: Vec<Step>,
+    /// Why steps were skipped, once per contract and reason.
+    pub diagnostics: Vec<Diagnostic>,
+}
 
 impl VecStep {
     pub fn push(&mut self, step: Step) {
-        self.0.push(step);
+        self.steps.push(step);
+    }
+
+    /// Records a step that could not be mapped to the sources.
+    pub fn skip(&mut self, diagnostic: Diagnostic) {
+        let seen = self
+            .diagnostics
+            .iter()
+            .any(|seen| seen.kind == diagnostic.kind && seen.address == diagnostic.address);
+        if !seen {
+            self.diagnostics.push(diagnostic);
+        }
     }
 }
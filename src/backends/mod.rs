@@ -1,2 +1,255 @@
+use crate::{
+    profile::{FrameKind, Weight},
+    replay::TestReplayer,
+    tree::CallTree,
+};
+use async_trait::async_trait;
+use debug::ast::FunctionIndex;
+use forge::{result::TestResult, traces::TraceKind};
+use foundry_common::compile::ContractSources;
+use foundry_evm_traces::{CallTraceArena, CallTraceDecoder};
+use revm_inspectors::tracing::types::CallKind;
+use std::{fmt, sync::Arc};
+
 pub mod call_trace;
 pub mod debug;
+pub mod timing;
+
+pub use call_trace::CallTraceBackend;
+pub use debug::DebugBackend;
+pub use timing::TimingBackend;
+
+/// Builds the call tree of a test.
+///
+/// Backends are registered in a [`BackendRegistry`] and selected with `--backend <name>`.
+#[async_trait]
+pub trait ProfileBackend: Send + Sync {
+    /// Name the backend is selected with, e.g. `calltrace`.
+    fn name(&self) -> &str;
+
+    /// One line description, listed by `forge-flamegraph backends`.
+    fn description(&self) -> &str;
+
+    /// Whether the tests have to be run with the debugger, which records every step. The
+    /// sources and the ast are then compiled as well.
+    fn needs_debug_steps(&self) -> bool {
+        false
+    }
+
+    /// Whether the backend replays the test itself, it is then given a [`TestReplayer`].
+    fn replays_tests(&self) -> bool {
+        false
+    }
+
+    /// What the frames are measured in if the backend decides it, e.g. time, `--weight`
+    /// otherwise.
+    fn weight(&self) -> Option<Weight> {
+        None
+    }
+
+    async fn profile(&self, input: &ProfileInput<'_>) -> eyre::Result<BackendProfile>;
+}
+
+/// What a backend gets to profile a test.
+pub struct ProfileInput<'a> {
+    /// Identifier of the test contract, e.g. `test/Vault.t.sol:VaultTest`.
+    pub contract: &'a str,
+    pub test_name: &'a str,
+    pub test_result: &'a TestResult,
+    /// Trace of the test result to profile, the test itself unless the deployment or `setUp`
+    /// of the test contract is included.
+    pub trace_kind: TraceKind,
+    /// Call trace to profile instead of the one of `trace_kind`, e.g. a sampled fuzz run.
+    pub trace: Option<&'a CallTraceArena>,
+    /// Decoder that identified the addresses of this test.
+    pub decoder: &'a CallTraceDecoder,
+    /// Sources of the test contract, only given to backends that need debug steps.
+    pub sources: Option<&'a ContractSources>,
+    /// Functions defined in the sources, empty unless the backend needs debug steps.
+    pub functions: &'a FunctionIndex,
+    pub merge_stacks: bool,
+    /// Replays the calls of the test, only given to backends that replay tests, or for
+    /// `--fuzz worst` with debug steps.
+    pub replayer: Option<&'a TestReplayer>,
+}
+
+/// Call tree built by a backend.
+pub struct BackendProfile {
+    pub tree: CallTree,
+    /// Whether the frames start where they ran within their parent, as when stepping through the
+    /// code. Call traces only tell the gas of each call, so their children are laid out one after
+    /// the other.
+    pub ordered: bool,
+    /// What went wrong while building the call tree, the tree may be partial if not empty.
+    pub diagnostics: Vec<String>,
+}
+
+impl From<CallTree> for BackendProfile {
+    fn from(tree: CallTree) -> Self {
+        Self {
+            tree,
+            ordered: false,
+            diagnostics: vec![],
+        }
+    }
+}
+
+/// Backends that can be selected by name.
+#[derive(Clone)]
+pub struct BackendRegistry {
+    backends: Vec<Arc<dyn ProfileBackend>>,
+}
+
+impl Default for BackendRegistry {
+    /// Returns the built-in backends.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(CallTraceBackend);
+        registry.register(DebugBackend);
+        registry.register(TimingBackend);
+        registry
+    }
+}
+
+impl fmt::Debug for BackendRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl BackendRegistry {
+    pub fn empty() -> Self {
+        Self { backends: vec![] }
+    }
+
+    /// Adds a backend, replacing a registered backend of the same name.
+    pub fn register(&mut self, backend: impl ProfileBackend + 'static) {
+        self.backends
+            .retain(|registered| registered.name() != backend.name());
+        self.backends.push(Arc::new(backend));
+    }
+
+    pub fn get(&self, name: &str) -> eyre::Result<&dyn ProfileBackend> {
+        self.backends
+            .iter()
+            .find(|backend| backend.name() == name)
+            .map(|backend| backend.as_ref())
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "unknown backend `{name}`, expected one of {}",
+                    self.names().collect::<Vec<_>>().join(", ")
+                )
+            })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn ProfileBackend> {
+        self.backends.iter().map(|backend| backend.as_ref())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|backend| backend.name())
+    }
+}
+
+impl From<CallKind> for FrameKind {
+    fn from(kind: CallKind) -> Self {
+        match kind {
+            CallKind::Call => FrameKind::Call,
+            CallKind::StaticCall => FrameKind::StaticCall,
+            CallKind::CallCode => FrameKind::CallCode,
+            CallKind::DelegateCall => FrameKind::DelegateCall,
+            CallKind::Create => FrameKind::Create,
+            CallKind::Create2 => FrameKind::Create2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tree::Frame;
+
+    struct Backend {
+        name: &'static str,
+        description: &'static str,
+    }
+
+    #[async_trait]
+    impl ProfileBackend for Backend {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn description(&self) -> &str {
+            self.description
+        }
+
+        async fn profile(&self, input: &ProfileInput<'_>) -> eyre::Result<BackendProfile> {
+            let root = Frame::new(input.test_name.to_string(), FrameKind::Call, 0);
+            Ok(CallTree::new(root).into())
+        }
+    }
+
+    #[test]
+    fn test_default_backends() {
+        let registry = BackendRegistry::default();
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["calltrace", "debug", "timing"]
+        );
+        assert!(!registry.get("calltrace").unwrap().needs_debug_steps());
+        assert!(registry.get("debug").unwrap().needs_debug_steps());
+        let timing = registry.get("timing").unwrap();
+        assert!(timing.replays_tests() && !timing.needs_debug_steps());
+        assert_eq!(timing.weight(), Some(Weight::Microseconds));
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = BackendRegistry::empty();
+        assert_eq!(registry.names().count(), 0);
+
+        registry.register(Backend {
+            name: "storage",
+            description: "storage slots",
+        });
+        registry.register(CallTraceBackend);
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["storage", "calltrace"]
+        );
+        let backend = registry.get("storage").unwrap();
+        assert_eq!(backend.name(), "storage");
+        assert_eq!(backend.description(), "storage slots");
+
+        // a backend of the same name replaces the registered one
+        registry.register(Backend {
+            name: "storage",
+            description: "storage slots, by contract",
+        });
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["calltrace", "storage"]
+        );
+        assert_eq!(
+            registry.get("storage").unwrap().description(),
+            "storage slots, by contract"
+        );
+    }
+
+    #[test]
+    fn test_unknown_backend() {
+        let registry = BackendRegistry::default();
+        let err = registry.get("storage").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unknown backend `storage`, expected one of calltrace, debug, timing"
+        );
+
+        let err = BackendRegistry::empty().get("calltrace").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unknown backend `calltrace`, expected one of "
+        );
+    }
+}
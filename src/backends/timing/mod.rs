@@ -0,0 +1,510 @@
+//! Wall-clock time of the EVM, recorded by an inspector while the test is replayed.
+//!
+//! forge's test runner doesn't take extra inspectors, so the [`TimingBackend`] deploys the test
+//! contract again on an executor of its own, see [`TestReplayer`](crate::replay::TestReplayer),
+//! and replays the test call with a [`TimingInspector`] wrapped around the inspectors of the
+//! executor. Cheatcodes keep working, and their time is part of the profile.
+
+use super::{call_trace::utils::get_display, BackendProfile, ProfileBackend, ProfileInput};
+use crate::{
+    profile::{FrameKind, Weight},
+    tree::{CallTree, Frame},
+};
+use alloy_primitives::{Address, Bytes, Log, U256};
+use async_trait::async_trait;
+use forge::{result::TestKind, traces::TraceKind};
+use foundry_evm::backend::CowBackend;
+use foundry_evm_traces::{CallTrace, CallTraceDecoder, CallTraceNode};
+use revm::{
+    interpreter::{
+        CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, CreateScheme, Interpreter,
+    },
+    primitives::TransactTo,
+    Database, EvmContext, Inspector,
+};
+use revm_inspectors::tracing::types::CallKind;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// Builds the call tree, measured in microseconds, by replaying the test call with a
+/// [`TimingInspector`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimingBackend;
+
+#[async_trait]
+impl ProfileBackend for TimingBackend {
+    fn name(&self) -> &str {
+        "timing"
+    }
+
+    fn description(&self) -> &str {
+        "wall-clock time of calls and opcode classes, from a replay of the test"
+    }
+
+    fn replays_tests(&self) -> bool {
+        true
+    }
+
+    fn weight(&self) -> Option<Weight> {
+        Some(Weight::Microseconds)
+    }
+
+    async fn profile(&self, input: &ProfileInput<'_>) -> eyre::Result<BackendProfile> {
+        let replayer = input
+            .replayer
+            .ok_or_else(|| eyre::eyre!("{} is not replayed", input.test_name))?;
+        eyre::ensure!(
+            input.trace_kind == TraceKind::Execution,
+            "only the test call of {} is replayed, not its setUp or deployment",
+            input.test_name
+        );
+        // a handler call depends on the state left by the calls before it
+        eyre::ensure!(
+            !matches!(input.test_result.kind, TestKind::Invariant { .. }),
+            "the calls of invariant tests can't be replayed one by one, {} is not timed",
+            input.test_name
+        );
+
+        // the test call, or the sampled fuzz run
+        let arena = input
+            .trace
+            .or_else(|| {
+                input
+                    .test_result
+                    .traces
+                    .iter()
+                    .find_map(|(kind, arena)| (*kind == TraceKind::Execution).then_some(arena))
+            })
+            .ok_or_else(|| eyre::eyre!("no call trace for {}", input.test_name))?;
+        let call = &arena.nodes()[0].trace;
+
+        // the executor is dropped before the calls are decoded
+        let profile = {
+            let mut executor = replayer.setup(input.contract, false)?;
+            let mut env = executor.build_test_env(
+                call.caller,
+                TransactTo::Call(call.address),
+                call.data.clone(),
+                call.value,
+            );
+            let mut inspector = TimingInspector::new(&mut executor.inspector);
+            let mut backend = CowBackend::new(&executor.backend);
+            backend.inspect(&mut env, &mut inspector)?;
+            inspector.into_profile()
+        };
+        let tree = profile.to_call_tree(input.decoder).await?;
+        Ok(tree.into())
+    }
+}
+
+/// Class of an opcode, the time a call spends in its own opcodes is broken down by class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OpcodeClass {
+    Arithmetic,
+    Bitwise,
+    Hashing,
+    Environment,
+    Block,
+    Stack,
+    Memory,
+    Storage,
+    Flow,
+    Log,
+    /// Calls, deployments, returns and reverts.
+    System,
+}
+
+impl OpcodeClass {
+    pub fn of(opcode: u8) -> Self {
+        match opcode {
+            0x01..=0x0B => OpcodeClass::Arithmetic,
+            0x10..=0x1D => OpcodeClass::Bitwise,
+            0x20 => OpcodeClass::Hashing,
+            0x30..=0x3F | 0x5A => OpcodeClass::Environment,
+            0x40..=0x4A => OpcodeClass::Block,
+            // POP, PUSH, DUP and SWAP
+            0x50 | 0x5F..=0x9F => OpcodeClass::Stack,
+            0x51..=0x53 | 0x59 | 0x5E => OpcodeClass::Memory,
+            0x54 | 0x55 | 0x5C | 0x5D => OpcodeClass::Storage,
+            0x00 | 0x56..=0x58 | 0x5B => OpcodeClass::Flow,
+            0xA0..=0xA4 => OpcodeClass::Log,
+            _ => OpcodeClass::System,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpcodeClass::Arithmetic => "arithmetic",
+            OpcodeClass::Bitwise => "bitwise",
+            OpcodeClass::Hashing => "hashing",
+            OpcodeClass::Environment => "environment",
+            OpcodeClass::Block => "block",
+            OpcodeClass::Stack => "stack",
+            OpcodeClass::Memory => "memory",
+            OpcodeClass::Storage => "storage",
+            OpcodeClass::Flow => "flow",
+            OpcodeClass::Log => "log",
+            OpcodeClass::System => "system",
+        }
+    }
+}
+
+/// A call or deployment timed by a [`TimingInspector`].
+#[derive(Clone, Debug)]
+pub struct TimedCall {
+    /// Index of the call this call was made by, `None` for the transaction.
+    pub parent: Option<usize>,
+    pub kind: CallKind,
+    /// Address of the code executed, the deployed contract for deployments.
+    pub address: Address,
+    pub input: Bytes,
+    /// Time from the start of the call to its end, including its children.
+    pub elapsed: Duration,
+    /// Time spent executing the opcodes of the call itself, by class.
+    pub opcodes: BTreeMap<OpcodeClass, Duration>,
+}
+
+/// Calls of a transaction timed by a [`TimingInspector`], parents before their children.
+#[derive(Clone, Debug, Default)]
+pub struct TimingProfile {
+    pub calls: Vec<TimedCall>,
+}
+
+impl TimingProfile {
+    /// Builds the call tree, measured in microseconds, with the calls named as by the call trace
+    /// backend, e.g. `Vault.deposit(uint256)`.
+    pub async fn to_call_tree(&self, decoder: &CallTraceDecoder) -> eyre::Result<CallTree> {
+        let mut names = vec![];
+        for call in &self.calls {
+            let node = CallTraceNode {
+                trace: CallTrace {
+                    address: call.address,
+                    kind: call.kind,
+                    data: call.input.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let decoded = decoder.decode_function(&node.trace).await;
+            names.push(get_display(&(&node, decoded)));
+        }
+        self.to_named_call_tree(names)
+    }
+
+    /// Builds the call tree, measured in microseconds, with the calls named `names`.
+    ///
+    /// The time a call spent in its own opcodes is shown as a frame per opcode class, e.g.
+    /// `[storage]`. The rest of its own time, e.g. in a precompile or the inspector, is left to
+    /// the call itself.
+    pub fn to_named_call_tree(&self, names: Vec<String>) -> eyre::Result<CallTree> {
+        eyre::ensure!(!self.calls.is_empty(), "no call was timed");
+        eyre::ensure!(
+            names.len() == self.calls.len(),
+            "{} names for {} calls",
+            names.len(),
+            self.calls.len()
+        );
+
+        let mut names = names.into_iter();
+        let frame = |call: &TimedCall, name| Frame {
+            address: Some(call.address),
+            ..Frame::new(name, call.kind.into(), 0)
+        };
+        let mut tree = CallTree::new(frame(&self.calls[0], names.next().unwrap_or_default()));
+        let mut child_time = vec![Duration::ZERO; self.calls.len()];
+        for (i, (call, name)) in self.calls.iter().zip(names).enumerate().skip(1) {
+            let parent = call
+                .parent
+                .filter(|parent| *parent < i)
+                .ok_or_else(|| eyre::eyre!("more than one transaction was timed"))?;
+            // calls are pushed first and in order, so the id of a call is its index
+            let parent_id = tree.nodes()[parent].id;
+            tree.push(parent_id, frame(call, name));
+            child_time[parent] += call.elapsed;
+        }
+
+        let mut self_weights = vec![0; self.calls.len()];
+        for (i, call) in self.calls.iter().enumerate() {
+            let call_id = tree.nodes()[i].id;
+            for (class, elapsed) in &call.opcodes {
+                tree.push(
+                    call_id,
+                    Frame {
+                        address: Some(call.address),
+                        ..Frame::new(format!("[{}]", class.name()), FrameKind::Opcode, 0)
+                    },
+                );
+                self_weights.push(micros(*elapsed));
+                child_time[i] += *elapsed;
+            }
+            self_weights[i] = micros(call.elapsed.saturating_sub(child_time[i]));
+        }
+        tree.lay_out(&self_weights);
+        Ok(tree)
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+/// Records the wall-clock time of every call and deployment of a transaction, and of the
+/// opcodes they execute by class, around the inspector `inner`.
+///
+/// The time of a call includes the hooks of `inner`, the time of an opcode doesn't.
+#[derive(Debug)]
+pub struct TimingInspector<I> {
+    inner: I,
+    profile: TimingProfile,
+    /// Calls being executed, innermost last, with when they started.
+    open: Vec<(usize, Instant)>,
+    /// Opcode being executed, with when it started.
+    step: Option<(u8, Instant)>,
+}
+
+impl<I> TimingInspector<I> {
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            profile: TimingProfile::default(),
+            open: vec![],
+            step: None,
+        }
+    }
+
+    pub fn into_profile(self) -> TimingProfile {
+        self.profile
+    }
+
+    fn enter(&mut self, kind: CallKind, address: Address, input: Bytes) {
+        self.profile.calls.push(TimedCall {
+            parent: self.open.last().map(|(parent, _)| *parent),
+            kind,
+            address,
+            input,
+            elapsed: Duration::ZERO,
+            opcodes: BTreeMap::new(),
+        });
+        self.open
+            .push((self.profile.calls.len() - 1, Instant::now()));
+    }
+
+    fn exit(&mut self, address: Option<Address>) {
+        let Some((i, started)) = self.open.pop() else {
+            return;
+        };
+        let call = &mut self.profile.calls[i];
+        call.elapsed = started.elapsed();
+        if let Some(address) = address {
+            call.address = address;
+        }
+    }
+}
+
+impl<DB: Database, I: Inspector<DB>> Inspector<DB> for TimingInspector<I> {
+    fn initialize_interp(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.initialize_interp(interp, context);
+    }
+
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.step(interp, context);
+        self.step = Some((interp.current_opcode(), Instant::now()));
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        // calls and deployments only start once their opcode is done
+        if let (Some((opcode, started)), Some((i, _))) = (self.step.take(), self.open.last()) {
+            *self.profile.calls[*i]
+                .opcodes
+                .entry(OpcodeClass::of(opcode))
+                .or_default() += started.elapsed();
+        }
+        self.inner.step_end(interp, context);
+    }
+
+    fn log(&mut self, context: &mut EvmContext<DB>, log: &Log) {
+        self.inner.log(context, log);
+    }
+
+    fn call(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        let kind = match inputs.context.scheme {
+            CallScheme::Call => CallKind::Call,
+            CallScheme::CallCode => CallKind::CallCode,
+            CallScheme::DelegateCall => CallKind::DelegateCall,
+            CallScheme::StaticCall => CallKind::StaticCall,
+        };
+        // `call_end` is called even if `inner` returns the outcome, e.g. of a cheatcode
+        let outcome = self.inner.call(context, inputs);
+        self.enter(kind, inputs.context.code_address, inputs.input.clone());
+        outcome
+    }
+
+    fn call_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.exit(None);
+        self.inner.call_end(context, inputs, outcome)
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let kind = match inputs.scheme {
+            CreateScheme::Create => CallKind::Create,
+            CreateScheme::Create2 { .. } => CallKind::Create2,
+        };
+        let outcome = self.inner.create(context, inputs);
+        // the address is known once the deployment is done
+        self.enter(kind, Address::ZERO, inputs.init_code.clone());
+        outcome
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.exit(outcome.address);
+        self.inner.create_end(context, inputs, outcome)
+    }
+
+    fn selfdestruct(&mut self, contract: Address, target: Address, value: U256) {
+        self.inner.selfdestruct(contract, target, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use revm::{
+        db::InMemoryDB,
+        inspector_handle_register,
+        inspectors::NoOpInspector,
+        primitives::{AccountInfo, Bytecode},
+        Evm,
+    };
+
+    #[test]
+    fn test_opcode_class() {
+        assert_eq!(OpcodeClass::of(0x01), OpcodeClass::Arithmetic);
+        assert_eq!(OpcodeClass::of(0x20), OpcodeClass::Hashing);
+        assert_eq!(OpcodeClass::of(0x54), OpcodeClass::Storage);
+        assert_eq!(OpcodeClass::of(0x60), OpcodeClass::Stack);
+        assert_eq!(OpcodeClass::of(0xF1), OpcodeClass::System);
+    }
+
+    #[test]
+    fn test_timing_inspector() {
+        // SSTORE(0, 42), then calls the precompile at 0x04 and stops
+        let code = Bytes::from_static(&[
+            0x60, 0x2a, 0x60, 0x00, 0x55, // PUSH1 42 PUSH1 0 SSTORE
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, // PUSH1 0 x5
+            0x60, 0x04, 0x5a, 0xf1, 0x00, // PUSH1 4 GAS CALL STOP
+        ]);
+        let address = Address::repeat_byte(0x11);
+        let mut db = InMemoryDB::default();
+        db.insert_account_info(
+            address,
+            AccountInfo {
+                code: Some(Bytecode::new_raw(code)),
+                ..Default::default()
+            },
+        );
+        let mut evm = Evm::builder()
+            .with_db(db)
+            .with_external_context(TimingInspector::new(NoOpInspector))
+            .modify_tx_env(|tx| tx.transact_to = TransactTo::Call(address))
+            .append_handler_register(inspector_handle_register)
+            .build();
+        evm.transact().unwrap();
+
+        let profile = evm.into_context().external.into_profile();
+        let calls = profile
+            .calls
+            .iter()
+            .map(|call| (call.parent, call.kind, call.address))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [
+                (None, CallKind::Call, address),
+                (Some(0), CallKind::Call, Address::with_last_byte(4)),
+            ]
+        );
+        let classes = profile.calls[0].opcodes.keys().copied().collect::<Vec<_>>();
+        assert_eq!(
+            classes,
+            [
+                OpcodeClass::Environment,
+                OpcodeClass::Stack,
+                OpcodeClass::Storage,
+                OpcodeClass::Flow,
+                OpcodeClass::System
+            ]
+        );
+        // the precompile runs no opcodes
+        assert!(profile.calls[1].opcodes.is_empty());
+    }
+
+    #[test]
+    fn test_to_named_call_tree() {
+        let call = |parent, elapsed, opcodes: &[(OpcodeClass, u64)]| TimedCall {
+            parent,
+            kind: CallKind::Call,
+            address: Address::ZERO,
+            input: Bytes::new(),
+            elapsed: Duration::from_micros(elapsed),
+            opcodes: opcodes
+                .iter()
+                .map(|(class, elapsed)| (*class, Duration::from_micros(*elapsed)))
+                .collect(),
+        };
+        let profile = TimingProfile {
+            calls: vec![
+                call(
+                    None,
+                    100,
+                    &[(OpcodeClass::Storage, 50), (OpcodeClass::Stack, 10)],
+                ),
+                call(Some(0), 30, &[(OpcodeClass::Arithmetic, 20)]),
+            ],
+        };
+        let names = vec![
+            "Vault.deposit(uint256)".to_string(),
+            "Token.mint()".to_string(),
+        ];
+        let tree = profile.to_named_call_tree(names).unwrap();
+
+        let frames = tree
+            .nodes()
+            .iter()
+            .map(|node| {
+                let frame = &node.frame;
+                (frame.name.as_str(), frame.gas_start, frame.gas_end)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            [
+                ("Vault.deposit(uint256)", 0, 100),
+                ("Token.mint()", 0, 30),
+                ("[stack]", 30, 40),
+                ("[storage]", 40, 90),
+                ("[arithmetic]", 0, 20),
+            ]
+        );
+        assert_eq!(tree.self_gas(tree.root()), 10);
+    }
+}
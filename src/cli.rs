@@ -2,12 +2,15 @@
 
 use super::{forge::install, forge::test::ProjectPathsAwareFilter};
 use clap::Parser;
-use eyre::Result;
+use eyre::{Result, WrapErr};
 use forge::{
     inspectors::CheatsConfig,
-    multi_runner::matches_contract,
-    result::{SuiteResult, TestOutcome, TestStatus},
-    traces::{identifier::SignaturesIdentifier, CallTraceDecoderBuilder, TraceKind},
+    multi_runner::{is_matching_test, matches_contract},
+    result::{SuiteResult, TestKind, TestOutcome, TestResult, TestStatus},
+    traces::{
+        identifier::SignaturesIdentifier, CallTrace, CallTraceArena, CallTraceDecoder,
+        CallTraceDecoderBuilder, TraceKind,
+    },
     MultiContractRunner, MultiContractRunnerBuilder, TestFilter, TestOptions, TestOptionsBuilder,
 };
 use foundry_cli::{
@@ -24,24 +27,41 @@ use foundry_compilers::{artifacts::output_selection::OutputSelection, utils::sou
 use foundry_config::{
     figment,
     figment::{
-        value::{Dict, Map},
-        Metadata, Profile, Provider,
+        value::{Dict, Map, Value},
+        Figment, Metadata, Provider,
     },
-    get_available_profiles, Config,
+    find_project_root_path, get_available_profiles, Config,
 };
 use foundry_evm::traces::identifier::TraceIdentifiers;
 use regex::Regex;
 use std::{
     collections::{BTreeMap, BTreeSet},
-    path::PathBuf,
+    fs,
+    path::{Path, PathBuf},
     sync::mpsc::channel,
 };
 use tracing::trace;
 // use watchexec::config::{InitConfig, RuntimeConfig};
 use yansi::Paint;
 
-use crate::flamegraph::Flamegraph;
 pub use crate::forge::test::FilterArgs;
+use crate::{
+    backends::{
+        debug::ast::FunctionIndex, BackendProfile, BackendRegistry, CallTraceBackend,
+        ProfileBackend, ProfileInput,
+    },
+    config::{FlamegraphConfig, FuzzMode, Include, InvariantMode},
+    diff::{self, GitWorktree},
+    flamegraph::{self, Flamegraph},
+    format::{self, OutputFormat},
+    index::{self, IndexEntry},
+    invariant,
+    output::OutputPaths,
+    profile::{FrameKind, GasCheck, Profile, ProfileMeta, Weight, UNATTRIBUTED},
+    replay::TestReplayer,
+    selector::TestSelector,
+    tree::{CallTree, Frame},
+};
 use forge::traces::render_trace_arena;
 
 foundry_config::merge_impl_figment_convert!(FlamegraphArgs, opts, evm_opts);
@@ -55,17 +75,41 @@ const VERSION_MESSAGE: &str = concat!(
     ")"
 );
 
+/// A test that was run, along with its profile.
+pub struct ProfiledTest {
+    /// Identifier of the test contract, e.g. `test/Vault.t.sol:VaultTest`.
+    pub contract: String,
+    pub test: String,
+    /// Total gas reported by the test result.
+    pub gas: u64,
+    pub profile: Result<Profile>,
+}
+
 /// CLI arguments for `forge test`.
 #[derive(Clone, Debug, Parser)]
 #[command(
     version = VERSION_MESSAGE,
     next_display_order = None,
+    args_conflicts_with_subcommands = true,
 )]
 pub struct FlamegraphArgs {
+    /// The test to profile, as `test`, `Contract::test` or `path:Contract::test`.
+    #[arg(value_name = "SELECTOR")]
+    selector: Option<TestSelector>,
+
     #[arg(long, short = 't', value_name = "TEST_FUNCTION")]
     test_function: Option<Regex>,
 
-    #[arg(long, short, help_heading = "Internal functions")]
+    /// Backend to profile the tests with, see `forge-flamegraph backends`. Defaults to calltrace.
+    #[arg(long, value_name = "NAME")]
+    backend: Option<String>,
+
+    #[arg(
+        long,
+        short,
+        help_heading = "Internal functions",
+        conflicts_with = "backend"
+    )]
     debugtrace: bool,
 
     #[arg(long, short, help_heading = "Open flamegraph in default program")]
@@ -74,6 +118,83 @@ pub struct FlamegraphArgs {
     #[arg(long, short, help_heading = "Merge stacks for flamegraph generation")]
     merge: bool,
 
+    /// Profile the tests at this git ref as well and write differential flamegraphs against it.
+    #[arg(long, value_name = "GIT_REF")]
+    diff_base: Option<String>,
+
+    /// Also profile the deployment of the test contract and its `setUp` function, drawn as
+    /// separate roots before the test.
+    #[arg(long, value_delimiter = ',', value_name = "PARTS")]
+    include: Vec<Include>,
+
+    /// Profile fuzz tests from the runs sampled for the gas report: their average, or the run
+    /// that used the most gas. The last run by default.
+    #[arg(long, value_name = "MODE")]
+    fuzz: Option<FuzzMode>,
+
+    /// Profile invariant tests as the average of the runs sampled for the gas report, or as the
+    /// failing sequence of calls (the last run if the invariants held). Average by default.
+    #[arg(long, value_name = "MODE")]
+    invariant: Option<InvariantMode>,
+
+    /// Measure the frames in gas or in executed instructions, which needs a backend that steps
+    /// through the code. Gas by default.
+    #[arg(long, value_name = "WEIGHT")]
+    weight: Option<Weight>,
+
+    /// Output formats to write, svg by default.
+    #[arg(long, value_delimiter = ',')]
+    format: Vec<OutputFormat>,
+
+    /// Directory to write the outputs to, or the path of the outputs of a single test, e.g.
+    /// `gas.svg`. Not `--out`, which is the artifacts directory of forge.
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Name of the outputs of each test, with the placeholders `{contract}`, `{test}`,
+    /// `{backend}`, `{timestamp}` and `{sha}`.
+    #[arg(long, value_name = "TEMPLATE")]
+    name: Option<String>,
+
+    /// Overwrite existing outputs.
+    #[arg(long)]
+    force: bool,
+
+    /// Also save the raw profile of each test, see the `report` command.
+    #[arg(long)]
+    save_profile: bool,
+
+    /// Only profile the tests that failed, e.g. to see where they reverted.
+    #[arg(long)]
+    failing_only: bool,
+
+    /// Fail when the debug trace can't be fully parsed, instead of writing a partial flamegraph.
+    #[arg(long)]
+    strict: bool,
+
+    /// Warn when the gas of the flamegraph differs from the gas reported by the test by more than
+    /// this percentage.
+    #[arg(long, value_name = "PERCENT", default_value_t = 1.0)]
+    gas_threshold: f64,
+
+    /// Flamegraph settings from `foundry.toml` overridden by the flags above, resolved in
+    /// [`Self::run`].
+    #[arg(skip)]
+    config: FlamegraphConfig,
+
+    /// Paths of the outputs, resolved in [`Self::run`].
+    #[arg(skip)]
+    paths: OutputPaths,
+
+    #[arg(skip)]
+    backends: BackendRegistry,
+
+    #[command(subcommand)]
+    pub command: Option<FlamegraphSubcommand>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
     #[command(flatten)]
     evm_opts: EvmArgs,
 
@@ -87,13 +208,88 @@ impl FlamegraphArgs {
         &self.opts
     }
 
-    pub async fn run(self) -> Result<TestOutcome> {
+    /// Makes a backend selectable with `--backend`, replacing a backend of the same name.
+    pub fn register_backend(&mut self, backend: impl ProfileBackend + 'static) {
+        self.backends.register(backend);
+    }
+
+    /// Prints the registered backends.
+    pub fn list_backends(&self) {
+        for backend in self.backends.iter() {
+            println!("{:<12} {}", backend.name(), backend.description());
+        }
+    }
+
+    pub async fn run(mut self) -> Result<TestOutcome> {
         trace!(target: "forge::test", "executing test command");
         // shell::set_shell(shell::Shell::from_args(self.opts.silent, self.json))?;
         shell::set_shell(shell::Shell::from_args(false, false))?; // TODO:
+        self.config = self.load_flamegraph_config()?;
+        self.check_weight()?;
+        self.paths = OutputPaths::new(
+            self.output.as_deref(),
+            self.config.out_dir.as_deref(),
+            self.config.name_template()?,
+            self.force,
+            &self.load_config().__root.0,
+        )?;
+        fs::create_dir_all(&self.paths.dir)?;
         self.execute_tests().await
     }
 
+    /// Checks that the selected backend can measure the frames in the selected weight, e.g. counts
+    /// instructions if the frames are weighted by them.
+    ///
+    /// Invariant tests are replayed from their call traces, so they keep their gas, see
+    /// [`Self::execution_weight`].
+    fn check_weight(&self) -> Result<()> {
+        let backend = self.backend()?;
+        if let Some(weight) = backend.weight() {
+            eyre::ensure!(
+                self.config.weight == Weight::Gas || self.config.weight == weight,
+                "the {} backend measures the frames in {}, `--weight` doesn't apply",
+                backend.name(),
+                weight.unit()
+            );
+            eyre::ensure!(
+                self.config.include.is_empty(),
+                "the {} backend only replays the test, not its setUp or deployment",
+                backend.name()
+            );
+        } else {
+            eyre::ensure!(
+                self.config.weight != Weight::Microseconds,
+                "time is only measured by replaying the test, try `--backend timing`"
+            );
+        }
+        if self.config.weight == Weight::Instructions {
+            eyre::ensure!(
+                backend.needs_debug_steps(),
+                "the {} backend does not count instructions, try `--backend debug`",
+                backend.name()
+            );
+            eyre::ensure!(
+                self.config.include.is_empty(),
+                "setUp and deployment are profiled from the call trace, which does not count \
+                 instructions"
+            );
+            eyre::ensure!(
+                self.config.fuzz != Some(FuzzMode::Average),
+                "averaged fuzz runs are profiled from their call traces, which do not count \
+                 instructions, try `--fuzz worst`"
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the `flamegraph` section of the selected profile in `foundry.toml`, with the flags
+    /// given on the command line taking precedence.
+    pub fn load_flamegraph_config(&self) -> Result<FlamegraphConfig> {
+        let config: FlamegraphConfig = Figment::from(self).extract_inner("flamegraph")?;
+        config.validate()?;
+        Ok(config)
+    }
+
     /// Returns sources which include any tests to be executed.
     /// If no filters are provided, sources are filtered by existence of test/invariant methods in
     /// them, If filters are provided, sources are additionaly filtered by them.
@@ -160,24 +356,209 @@ impl FlamegraphArgs {
         Ok(test_sources)
     }
 
-    /// Executes all the tests in the project.
+    /// Executes all the tests in the project and writes a flamegraph for each of them.
     ///
     /// This will trigger the build process first. On success all test contracts that match the
     /// configured filter will be executed
     ///
     /// Returns the test results for all matching tests.
     pub async fn execute_tests(self) -> Result<TestOutcome> {
+        if let Some(base) = &self.diff_base {
+            return self.execute_diff(base).await;
+        }
+
+        let (outcome, profiled) = self.profile_tests().await?;
+        if profiled.is_empty() && self.failing_only {
+            println!("No test failed, nothing to profile");
+            return Ok(outcome);
+        }
+        if self.paths.file.is_some() && profiled.len() > 1 {
+            eyre::bail!(
+                "--output names a single file but {} tests were profiled, pass a directory instead",
+                profiled.len()
+            );
+        }
+        let out_dir = &self.paths.dir;
+
+        let mut stems = BTreeSet::new();
+        let entries = profiled
+            .into_iter()
+            .map(|profiled| {
+                let mut entry = IndexEntry {
+                    contract: profiled.contract,
+                    test: profiled.test,
+                    gas: profiled.gas,
+                    file: None,
+                    error: None,
+                };
+                let stem = self.file_stem(&entry.contract, &entry.test);
+                // tests of contracts with the same name in different files
+                let written = if stems.insert(stem.clone()) {
+                    profiled
+                        .profile
+                        .and_then(|profile| self.write_outputs(&stem, &profile))
+                } else {
+                    Err(eyre::eyre!(
+                        "{stem} is the name of another test's outputs too, pass a --name that \
+                         tells them apart"
+                    ))
+                };
+                match written {
+                    // the index is written to the output directory as well
+                    Ok(path) => {
+                        let path = Path::new(&path);
+                        let file = path.strip_prefix(out_dir).unwrap_or(path);
+                        entry.file = Some(file.to_string_lossy().into_owned());
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "{} failed to generate flamegraph for {}: {err}",
+                            Paint::red("Error:").bold(),
+                            entry.test
+                        );
+                        entry.error = Some(err.to_string());
+                    }
+                }
+                entry
+            })
+            .collect::<Vec<_>>();
+
+        let generated = entries.iter().filter(|entry| entry.file.is_some()).count();
+        if generated == 0 {
+            eyre::bail!("no flamegraph could be generated");
+        }
+
+        // A single test keeps pointing to its flamegraph, multiple tests get an index page.
+        let file_to_open = if entries.len() == 1 {
+            out_dir.join(entries[0].file.as_ref().unwrap())
+        } else {
+            let index_file = out_dir.join("flamegraph_index.html");
+            index::write_index(&index_file, &entries)?;
+            println!(
+                "\n{generated} of {} flamegraphs generated, see {}",
+                entries.len(),
+                index_file.display()
+            );
+            index_file
+        };
+
+        println!("\nFlamegraph generated!");
+
+        if self.open {
+            open::that(file_to_open)?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Profiles the tests at `base` in a temporary git worktree and in the current working tree,
+    /// and writes a differential flamegraph for every test found in both.
+    async fn execute_diff(&self, base: &str) -> Result<TestOutcome> {
+        let config = self.load_config();
+        let worktree = GitWorktree::new(&config.__root.0, base)?;
+        println!("Profiling {base} in {}", worktree.project_root().display());
+
+        let mut base_args = self.clone();
+        base_args.diff_base = None;
+        base_args.opts.project_paths.root = Some(worktree.project_root().to_path_buf());
+        let (_, base_profiled) = base_args
+            .profile_tests()
+            .await
+            .wrap_err_with(|| format!("failed to profile base `{base}`"))?;
+
+        println!("\nProfiling the working tree");
+        let (outcome, profiled) = self
+            .profile_tests()
+            .await
+            .wrap_err("failed to profile the working tree")?;
+
+        let mut generated = vec![];
+        for profiled in profiled {
+            let Ok(after) = profiled.profile else {
+                continue;
+            };
+            let Some(before) = base_profiled
+                .iter()
+                .find(|base| base.contract == profiled.contract && base.test == profiled.test)
+                .and_then(|base| base.profile.as_ref().ok())
+            else {
+                println!(
+                    "{} {}::{} does not exist at {base}, skipping",
+                    Paint::yellow("Warning:").bold(),
+                    profiled.contract,
+                    profiled.test
+                );
+                continue;
+            };
+
+            // invariant tests are measured in gas, see `execution_weight`
+            let unit = after.meta.weight.unit();
+            let before = Flamegraph::from_call_tree(&before.tree);
+            let after = Flamegraph::from_call_tree(&after.tree);
+
+            let stem = self.file_stem(&profiled.contract, &profiled.test);
+            let file_name = format!("{stem}_diff.svg");
+            let summary_file = format!("{stem}_diff.txt");
+            self.paths.check_overwrite(Path::new(&file_name))?;
+            self.paths.check_overwrite(Path::new(&summary_file))?;
+            create_parent_dir(&stem)?;
+
+            let mut options = flamegraph::Options {
+                title: file_name.clone(),
+                subtitle: Some(format!(
+                    "{base} → working tree, {unit} {} → {}",
+                    before.total_gas(),
+                    after.total_gas()
+                )),
+                count_name: unit.to_string(),
+                ..Default::default()
+            };
+            let file = fs::File::create(&file_name)?;
+            diff::write_differential(
+                &before.folded_stack_lines,
+                &after.folded_stack_lines,
+                &mut options,
+                file,
+            )?;
+
+            let summary = diff::summary(
+                &diff::self_gas_changes(&before.folded_stack_lines, &after.folded_stack_lines),
+                20,
+            );
+            println!("\n{}::{}\n{summary}", profiled.contract, profiled.test);
+            fs::write(summary_file, summary)?;
+
+            generated.push(file_name);
+        }
+
+        if generated.is_empty() {
+            eyre::bail!("no test could be profiled at both {base} and the working tree");
+        }
+
+        println!("\nDifferential flamegraph generated!");
+
+        if self.open {
+            for file_name in generated {
+                open::that(file_name)?;
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Compiles the project, runs all the tests that match the configured filter and builds the
+    /// flamegraph of each of them.
+    ///
+    /// A test that cannot be profiled does not stop the others, its error is kept instead.
+    pub async fn profile_tests(&self) -> Result<(TestOutcome, Vec<ProfiledTest>)> {
+        let backend = self.backend()?;
+
         // Merge all configs
         let (mut config, mut evm_opts) = self.load_config_and_evm_opts_emit_warnings()?;
-
-        // Explicitly enable isolation for gas reports for more correct gas accounting
-        // if self.gas_report {
-        //     evm_opts.isolate = true;
-        // } else {
-        //     // Do not collect gas report traces if gas report is not enabled.
-        //     config.fuzz.gas_report_samples = 0;
-        //     config.invariant.gas_report_samples = 0;
-        // }
+        // internal functions are named after their definitions in the ast
+        if backend.needs_debug_steps() {
+            config.ast = true;
+        }
 
         // Set up the project.
         let mut project = config.project()?;
@@ -188,10 +569,13 @@ impl FlamegraphArgs {
         {
             // need to re-configure here to also catch additional remappings
             config = self.load_config();
+            if backend.needs_debug_steps() {
+                config.ast = true;
+            }
             project = config.project()?;
         }
 
-        let mut filter = self.filter(&config);
+        let filter = self.filter(&config)?;
         trace!(target: "forge::test", ?filter, "using filter");
 
         let sources_to_compile = self.get_sources_to_compile(&config, &filter)?;
@@ -224,7 +608,7 @@ impl FlamegraphArgs {
         let env = evm_opts.evm_env().await?;
 
         // Prepare the test builder
-        let should_debug = self.debugtrace;
+        let should_debug = backend.needs_debug_steps();
 
         // Clone the output only if we actually need it later for the debugger.
         let output_clone = should_debug.then(|| output.clone());
@@ -248,120 +632,549 @@ impl FlamegraphArgs {
             .enable_isolation(evm_opts.isolate)
             .build(project_root, output, env, evm_opts)?;
 
-        if let Some(debug_test_pattern) = &self.test_function {
-            let test_pattern = &mut filter.args_mut().test_pattern;
-            if test_pattern.is_some() {
-                eyre::bail!(
-                    "Cannot specify both --debug and --match-test. \
-                     Use --match-contract and --match-path to further limit the search instead."
+        let known_contracts = runner.known_contracts.clone();
+        // the runner only records the debug steps of the last run of a fuzz test
+        let replays =
+            backend.replays_tests() || (should_debug && self.config.fuzz == Some(FuzzMode::Worst));
+        let replayer = replays.then(|| TestReplayer::new(&runner));
+        let mut outcome = self.run_tests(runner, config, verbosity, &filter).await?;
+
+        let Some(mut decoder) = outcome.decoder.take() else {
+            eyre::bail!("no tests were executed");
+        };
+        let mut identifier = TraceIdentifiers::new().with_local(&known_contracts);
+
+        let functions = output_clone
+            .as_ref()
+            .map(FunctionIndex::from_project_output)
+            .unwrap_or_default();
+
+        // Paths of the source files by source id, to resolve the locations of debug frames.
+        let source_paths = functions.source_paths(project.root());
+        let ambiguous = functions.ambiguous().collect::<Vec<_>>();
+        if !ambiguous.is_empty() {
+            eprintln!(
+                "{} source ids {ambiguous:?} are used by more than one compiler run, functions \
+                 and locations in those files are not resolved",
+                Paint::yellow("Warning:").bold()
+            );
+        }
+
+        println!();
+        let mut profiled = vec![];
+        for (contract_name, suite_result) in &outcome.results {
+            // flamegraph inputs: debug, sources
+            let sources = match output_clone
+                .as_ref()
+                .map(|output| {
+                    ContractSources::from_project_output(
+                        output,
+                        project.root(),
+                        &suite_result.libraries,
+                    )
+                })
+                .transpose()
+            {
+                Ok(sources) => sources,
+                Err(err) => {
+                    for (test_name, test_result) in suite_result
+                        .test_results
+                        .iter()
+                        .filter(|(_, test_result)| self.is_profiled(test_result))
+                    {
+                        profiled.push(ProfiledTest {
+                            contract: contract_name.clone(),
+                            test: test_name.clone(),
+                            gas: test_result.kind.report().gas(),
+                            profile: Err(eyre::eyre!("{err}")),
+                        });
+                    }
+                    continue;
+                }
+            };
+
+            for (test_name, test_result) in suite_result
+                .test_results
+                .iter()
+                .filter(|(_, test_result)| self.is_profiled(test_result))
+            {
+                // The decoder only knows the addresses of the last test that ran, so identify
+                // the addresses of this test again before decoding its traces.
+                decoder.clear_addresses();
+                decoder.labels.extend(
+                    test_result
+                        .labeled_addresses
+                        .iter()
+                        .map(|(k, v)| (*k, v.clone())),
                 );
+                for (_, arena) in &test_result.traces {
+                    decoder.identify(arena, &mut identifier);
+                }
+                if self.config.fuzz.is_some()
+                    || matches!(test_result.kind, TestKind::Invariant { .. })
+                {
+                    for arena in test_result.gas_report_traces.iter().flatten() {
+                        decoder.identify(arena, &mut identifier);
+                    }
+                }
+
+                let mut spinner = Spinner::new(format!("Flamingraph {test_name}..."));
+                spinner.tick();
+
+                let mut meta = ProfileMeta {
+                    contract: contract_name.clone(),
+                    test: test_name.clone(),
+                    backend: self.backend_name().to_string(),
+                    status: format!("{:?}", test_result.status),
+                    gas: test_result.kind.report().gas(),
+                    merge_stacks: self.config.merge,
+                    root: Some(project.root().to_path_buf()),
+                    subtitle: None,
+                    weight: self.config.weight,
+                    ordered: false,
+                };
+                let input = ProfileInput {
+                    contract: contract_name,
+                    test_name,
+                    test_result,
+                    trace_kind: TraceKind::Execution,
+                    trace: None,
+                    decoder: &decoder,
+                    sources: sources.as_ref(),
+                    functions: &functions,
+                    merge_stacks: self.config.merge,
+                    replayer: replayer.as_ref(),
+                };
+                let backend = self.execution_backend(backend, &input);
+                meta.backend = backend.name().to_string();
+                meta.weight = self.execution_weight(backend, test_name);
+                let execution = self
+                    .profile_execution(backend, &input)
+                    .await
+                    .and_then(|execution| self.weigh(meta.weight, execution));
+                let profile = match execution {
+                    Ok(mut execution) => {
+                        // fuzz and invariant tests report aggregates of many runs
+                        if let TestKind::Standard(gas) = test_result.kind {
+                            if meta.weight == Weight::Gas {
+                                let check = execution.tree.reconcile_gas(gas);
+                                self.report_gas_check(test_name, &check);
+                            }
+                        }
+                        meta.subtitle = execution.runs;
+                        // averaged runs have no execution order
+                        meta.merge_stacks |= execution.averaged;
+                        // included traces are laid out from their call traces
+                        meta.ordered = execution.ordered && self.config.include.is_empty();
+                        self.include_traces(backend, &input, contract_name, execution.tree)
+                            .await
+                            .map(|tree| Profile::new(meta, source_paths.clone(), tree))
+                    }
+                    Err(err) => Err(err),
+                };
+
+                profiled.push(ProfiledTest {
+                    contract: contract_name.clone(),
+                    test: test_name.clone(),
+                    gas: test_result.kind.report().gas(),
+                    profile,
+                });
             }
-            *test_pattern = Some(debug_test_pattern.clone());
         }
 
-        let outcome = self.run_tests(runner, config, verbosity, &filter).await?;
-
-        // flamegraph inputs: debug, sources
+        outcome.decoder = Some(decoder);
 
-        let Some((suite_result, test_result)) = outcome
-            .results
-            .iter()
-            .find(|(_, r)| !r.test_results.is_empty())
-            .map(|(_, r)| (r, r.test_results.values().next().unwrap()))
-        else {
-            return Err(eyre::eyre!("no tests were executed"));
-        };
+        Ok((outcome, profiled))
+    }
 
-        let keys: Vec<String> = suite_result.test_results.keys().cloned().collect();
-        assert_eq!(keys.len(), 1, "number of tests ran must be 1");
-        let mut test_name = keys[0].clone();
-        if test_name.ends_with("()") {
-            test_name.truncate(test_name.len() - 2);
+    /// Returns what the frames of a test are measured in, as the backend decides or by
+    /// `--weight`. Tests profiled by a backend that does not count instructions, i.e. invariant
+    /// runs replayed from their call traces, are measured in gas with a warning.
+    fn execution_weight(&self, backend: &dyn ProfileBackend, test_name: &str) -> Weight {
+        if let Some(weight) = backend.weight() {
+            return weight;
         }
+        if self.config.weight == Weight::Instructions && !backend.needs_debug_steps() {
+            eprintln!(
+                "{} the {} backend does not count instructions, {test_name} is measured in gas",
+                Paint::yellow("Warning:").bold(),
+                backend.name()
+            );
+            return Weight::Gas;
+        }
+        self.config.weight
+    }
 
-        println!();
-        let mut spinner = Spinner::new("Flamingraph...");
-        spinner.tick();
-
-        let mut flamegraph = if should_debug {
-            // Get first non-empty suite result. We will have only one such entry
+    /// Measures the frames in instructions if they are weighted by them.
+    fn weigh(&self, weight: Weight, mut execution: Execution) -> Result<Execution> {
+        if weight == Weight::Instructions {
+            execution.tree.weigh_by_instructions()?;
+        }
+        Ok(execution)
+    }
 
-            let sources = ContractSources::from_project_output(
-                output_clone.as_ref().unwrap(),
-                project.root(),
-                &suite_result.libraries,
-            )?;
+    /// Returns whether a test that ran gets profiled, all of them unless `--failing-only`.
+    fn is_profiled(&self, test_result: &TestResult) -> bool {
+        !self.failing_only || test_result.status == TestStatus::Failure
+    }
 
-            Flamegraph::from_debug_trace(
-                sources,
-                test_result,
-                outcome.decoder.as_ref().unwrap(),
-                self.merge,
-            )?
-
-            // let mut builder = Debugger::builder()
-            //     .debug_arenas(test_result.debug.as_slice())
-            //     .sources(sources)
-            //     .breakpoints(test_result.breakpoints.clone());
-            // // identified contracts are set here
-            // if let Some(decoder) = &outcome.decoder {
-            //     builder = builder.decoder(decoder);
-            // }
-            // let mut debugger = builder.build();
-
-            // let mut steps = VecStep::default();
-            // if self.steps {
-            //     println!("steps {:#?}", steps);
-            // }
-
-            // // debugger.run_silent()?;
-            // debugger.try_run(&mut steps)?;
-
-            // let top_call = steps.parse();
-
-            // if self.json {
-            //     println!(
-            //         "\n\nflamegraph data: {}\n\n",
-            //         serde_json::to_string_pretty(&top_call).unwrap()
-            //     );
-            // } else {
-            //     println!("\n\nflamegraph data: {:?}\n\n", top_call);
-            // }
+    /// Adds the deployment and `setUp` traces selected with `--include` before the test, as
+    /// roots of their own under the test contract.
+    async fn include_traces(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+        contract: &str,
+        test: CallTree,
+    ) -> Result<CallTree> {
+        if self.config.include.is_empty() {
+            return Ok(test);
+        }
+        let contract = contract.rsplit(':').next().unwrap_or(contract);
+        let mut tree = CallTree::new(Frame::new(contract.to_string(), FrameKind::Call, 0));
+        // the debugger only records the steps of the test itself
+        let backend: &dyn ProfileBackend = if backend.needs_debug_steps() {
+            &CallTraceBackend
         } else {
-            let arena = test_result
+            backend
+        };
+
+        let included = self.config.include.iter().collect::<BTreeSet<_>>();
+        for include in included {
+            let (trace_kind, name) = match include {
+                Include::Deployment => (TraceKind::Deployment, format!("[deploy {contract}]")),
+                Include::Setup => (TraceKind::Setup, "[setUp]".to_string()),
+            };
+            // contracts without a `setUp` function have no setup trace
+            if !input
+                .test_result
                 .traces
                 .iter()
-                .find_map(|(kind, arena)| {
-                    if *kind == TraceKind::Execution {
-                        Some(arena)
-                    } else {
-                        None
-                    }
+                .any(|(kind, _)| *kind == trace_kind)
+            {
+                continue;
+            }
+            let input = ProfileInput {
+                trace_kind,
+                ..*input
+            };
+            let mut trace = self.profile_test(backend, &input).await?.tree;
+            trace.frame_mut(trace.root()).name = name;
+            tree.append(tree.root(), &trace);
+        }
+        tree.append(tree.root(), &test);
+
+        Ok(tree)
+    }
+
+    /// Returns the backend that profiles the runs of the test.
+    ///
+    /// The debugger only records the steps of the last run, so backends that need them can't
+    /// profile the average of the runs sampled for `--fuzz average` or the calls of invariant
+    /// tests. These are profiled from their call traces instead, with a warning. The run picked
+    /// by `--fuzz worst` is replayed with the debugger.
+    fn execution_backend<'b>(
+        &self,
+        backend: &'b dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+    ) -> &'b dyn ProfileBackend {
+        let sampled = match input.test_result.kind {
+            TestKind::Fuzz { .. } => self.config.fuzz == Some(FuzzMode::Average),
+            TestKind::Invariant { .. } => true,
+            _ => false,
+        };
+        if !sampled || !backend.needs_debug_steps() {
+            return backend;
+        }
+        eprintln!(
+            "{} the {} backend can't replay the runs of {}, they are profiled by the {} backend",
+            Paint::yellow("Warning:").bold(),
+            backend.name(),
+            input.test_name,
+            CallTraceBackend.name()
+        );
+        &CallTraceBackend
+    }
+
+    /// Builds the call tree of the test itself. With `--fuzz`, fuzz tests are profiled from the
+    /// runs sampled for the gas report instead. Invariant tests are profiled as sequences of
+    /// calls, see `--invariant`.
+    async fn profile_execution(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+    ) -> Result<Execution> {
+        match (&input.test_result.kind, self.config.fuzz) {
+            (TestKind::Fuzz { .. }, Some(mode)) => {
+                self.profile_fuzz_runs(backend, input, mode).await
+            }
+            (TestKind::Invariant { .. }, _) => self.profile_invariant(backend, input).await,
+            _ => {
+                let profile = self.profile_test(backend, input).await?;
+                Ok(Execution {
+                    tree: profile.tree,
+                    runs: None,
+                    averaged: false,
+                    ordered: profile.ordered,
+                })
+            }
+        }
+    }
+
+    async fn profile_fuzz_runs(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+        mode: FuzzMode,
+    ) -> Result<Execution> {
+        let runs = input
+            .test_result
+            .gas_report_traces
+            .iter()
+            .filter_map(|traces| traces.last())
+            .collect::<Vec<_>>();
+        match mode {
+            FuzzMode::Average => {
+                let mut trees = vec![];
+                for run in &runs {
+                    let input = ProfileInput {
+                        trace: Some(*run),
+                        ..*input
+                    };
+                    trees.push(self.profile_test(backend, &input).await?.tree);
+                }
+                Ok(Execution {
+                    tree: CallTree::average(&trees).ok_or_else(|| no_sampled_runs(input))?,
+                    runs: Some(format!("average of {} runs", runs.len())),
+                    averaged: true,
+                    ordered: false,
                 })
-                .unwrap();
+            }
+            FuzzMode::Worst => {
+                let worst = runs
+                    .iter()
+                    .copied()
+                    .max_by_key(|run| run.nodes().first().map(|node| node.trace.gas_used))
+                    .ok_or_else(|| no_sampled_runs(input))?;
+                let profile = if backend.needs_debug_steps() {
+                    self.replay_test(backend, input, &worst.nodes()[0].trace)
+                        .await?
+                } else {
+                    let input = ProfileInput {
+                        trace: Some(worst),
+                        ..*input
+                    };
+                    self.profile_test(backend, &input).await?
+                };
+                Ok(Execution {
+                    tree: profile.tree,
+                    runs: Some(format!(
+                        "most expensive of {} runs, inputs: {}",
+                        runs.len(),
+                        fuzz_inputs(input.decoder, worst).await
+                    )),
+                    averaged: false,
+                    ordered: profile.ordered,
+                })
+            }
+        }
+    }
 
-            let nodes = arena.nodes();
-            let decoder = outcome.decoder.as_ref().unwrap();
-            Flamegraph::from_call_trace(nodes, decoder).await
+    /// Replays `call` with the debugger and profiles it, for backends that need the debug steps
+    /// of a run forge didn't record.
+    async fn replay_test(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+        call: &CallTrace,
+    ) -> Result<BackendProfile> {
+        let replayer = input
+            .replayer
+            .ok_or_else(|| eyre::eyre!("the runs of {} are not replayed", input.test_name))?;
+        let replayed = replayer.replay(input.contract, call, true)?;
+        let test_result = TestResult {
+            traces: replayed
+                .traces
+                .map(|arena| vec![(TraceKind::Execution, arena)])
+                .unwrap_or_default(),
+            debug: replayed.debug,
+            ..input.test_result.clone()
         };
+        let input = ProfileInput {
+            test_result: &test_result,
+            trace: None,
+            ..*input
+        };
+        self.profile_test(backend, &input).await
+    }
+
+    /// Profiles the handler calls of an invariant test, each call a child of the root.
+    async fn profile_invariant(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+    ) -> Result<Execution> {
+        match self.config.invariant {
+            InvariantMode::Average => {
+                let runs = &input.test_result.gas_report_traces;
+                let mut trees = vec![];
+                for run in runs {
+                    trees.push(self.profile_sequence(backend, input, run.iter()).await?);
+                }
+                Ok(Execution {
+                    tree: CallTree::average(&trees).ok_or_else(|| no_sampled_runs(input))?,
+                    runs: Some(format!("average of {} runs", runs.len())),
+                    averaged: true,
+                    ordered: false,
+                })
+            }
+            InvariantMode::Sequence => {
+                // the failing sequence, or the last run, replayed with the invariant checks
+                let calls = invariant::sequence_calls(&input.test_result.traces);
+                Ok(Execution {
+                    tree: self
+                        .profile_sequence(backend, input, calls.iter().copied())
+                        .await?,
+                    runs: Some(invariant::sequence_name(
+                        &input.test_result.status,
+                        calls.len(),
+                    )),
+                    averaged: false,
+                    // the calls are laid out one after the other
+                    ordered: false,
+                })
+            }
+        }
+    }
+
+    /// Profiles a sequence of calls under a root named after the test.
+    async fn profile_sequence<'a>(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+        calls: impl Iterator<Item = &'a CallTraceArena>,
+    ) -> Result<CallTree> {
+        let mut trees = vec![];
+        for call in calls {
+            let input = ProfileInput {
+                trace: Some(call),
+                ..*input
+            };
+            trees.push(self.profile_test(backend, &input).await?.tree);
+        }
+        Ok(invariant::sequence_tree(input.test_name, &trees))
+    }
+
+    /// Builds the call tree of a single test with the selected backend, and reports its
+    /// diagnostics.
+    async fn profile_test(
+        &self,
+        backend: &dyn ProfileBackend,
+        input: &ProfileInput<'_>,
+    ) -> Result<BackendProfile> {
+        let test_name = input.test_name;
+        let profile = backend.profile(input).await?;
+        if !profile.diagnostics.is_empty() {
+            let diagnostics = profile
+                .diagnostics
+                .iter()
+                .map(|diagnostic| format!("  {diagnostic}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if self.strict {
+                eyre::bail!(
+                    "the {} trace of {test_name} could not be fully parsed:\n{diagnostics}",
+                    backend.name()
+                );
+            }
+            eprintln!(
+                "{} the flamegraph of {test_name} may be incomplete:\n{diagnostics}",
+                Paint::yellow("Warning:").bold()
+            );
+        }
+        Ok(profile)
+    }
 
-        // println!("flamegraph: {:#?}", flamegraph.folded_stack_lines);
+    /// Reports the gas of a test missing from its flamegraph, with a warning above the threshold.
+    fn report_gas_check(&self, test_name: &str, check: &GasCheck) {
+        let difference = check.difference();
+        if difference == 0 && check.overlapping == 0 {
+            return;
+        }
 
-        let file_name = format!(
-            "flamegraph_{}_{}.svg",
-            test_name,
-            if should_debug { "debug" } else { "calltrace" }
+        let mut message = format!(
+            "{test_name} reports {} gas, the flamegraph has {}",
+            check.reported, check.profiled
         );
+        if difference > 0 {
+            message.push_str(&format!(", {difference} gas shown as {UNATTRIBUTED}"));
+        }
+        if check.overlapping > 0 {
+            message.push_str(&format!(
+                ", {} gas of calls exceeds their caller",
+                check.overlapping
+            ));
+        }
 
-        flamegraph.generate(&file_name, self.merge);
+        if check.exceeds(self.gas_threshold) {
+            eprintln!("{} {message}", Paint::yellow("Warning:").bold());
+        } else {
+            println!("{message}");
+        }
+    }
 
-        println!("\nFlamegraph generated!");
+    /// Writes the outputs of a test, and its raw profile if requested, and returns the path of
+    /// the first output.
+    fn write_outputs(&self, stem: &str, profile: &Profile) -> Result<String> {
+        // nothing is written if any of the outputs exists
+        let mut paths = self
+            .config
+            .formats
+            .iter()
+            .map(|format| format!("{stem}.{}", format.extension()))
+            .collect::<Vec<_>>();
+        if self.save_profile {
+            paths.push(format!("{stem}.profile.json"));
+        }
+        for path in &paths {
+            self.paths.check_overwrite(Path::new(path))?;
+        }
+        create_parent_dir(stem)?;
 
-        if self.open {
-            open::that(file_name)?;
+        if self.save_profile {
+            profile.save(format!("{stem}.profile.json"))?;
         }
 
-        Ok(outcome)
+        // the saved profile keeps the original names, to be transformed again when rendered
+        let mut profile = profile.clone();
+        self.config.transform_names(&mut profile.tree)?;
+
+        let mut files = self
+            .config
+            .formats
+            .iter()
+            .map(|format| format::write_output(&profile, *format, stem, self.config.svg_options()?))
+            .collect::<Result<Vec<_>>>()?;
+        eyre::ensure!(!files.is_empty(), "no output format given");
+        Ok(files.swap_remove(0))
+    }
+
+    /// Returns the name of the backend used to profile the tests.
+    fn backend_name(&self) -> &str {
+        &self.config.backend
+    }
+
+    /// Returns the backend used to profile the tests.
+    fn backend(&self) -> Result<&dyn ProfileBackend> {
+        self.backends.get(&self.config.backend)
+    }
+
+    /// Returns the path, without extension, of the outputs generated for a test.
+    fn file_stem(&self, contract: &str, test_name: &str) -> String {
+        self.paths
+            .stem(contract, test_name, self.backend_name())
+            .to_string_lossy()
+            .into_owned()
     }
 
     /// Run all tests that matches the filter predicate from a test runner
@@ -375,12 +1188,32 @@ impl FlamegraphArgs {
         trace!(target: "forge::test", "running all tests");
 
         let num_filtered = runner.matching_test_functions(filter).count();
-        if num_filtered != 1 {
-            eyre::bail!(
-                "{num_filtered} tests matched your criteria, but exactly 1 test must match in order to run the debugger.\n\n\
-                 Use --match-contract and --match-path to further limit the search.\n\
-                 Filter used:\n{filter}"
-            );
+        if num_filtered == 0 {
+            eyre::bail!("No tests matched your criteria.\n\nFilter used:\n{filter}");
+        }
+
+        // A selector names exactly one test, so it must not resolve to several candidates.
+        if let Some(selector) = &self.selector {
+            let candidates = runner
+                .matching_contracts(filter)
+                .flat_map(|(id, contract)| {
+                    contract
+                        .abi
+                        .functions()
+                        .filter(|func| is_matching_test(func, filter))
+                        .map(move |func| {
+                            format!("{}:{}::{}", id.source.display(), id.name, func.name)
+                        })
+                })
+                .collect::<Vec<_>>();
+            if candidates.len() > 1 {
+                eyre::bail!(
+                    "Selector `{selector}` is ambiguous, it matches {} tests:\n\t{}\n\n\
+                     Use `path:Contract::test` to pick one.",
+                    candidates.len(),
+                    candidates.join("\n\t")
+                );
+            }
         }
 
         // Set up trace identifiers.
@@ -523,9 +1356,29 @@ impl FlamegraphArgs {
         Ok(outcome)
     }
 
-    // /// Returns the flattened [`FilterArgs`] arguments merged with [`Config`].
-    pub fn filter(&self, config: &Config) -> ProjectPathsAwareFilter {
-        FilterArgs::default().clone().merge_with_config(config)
+    /// Returns the flattened [`FilterArgs`] arguments, narrowed down by the test selector and
+    /// `--test-function`, merged with [`Config`].
+    pub fn filter(&self, config: &Config) -> Result<ProjectPathsAwareFilter> {
+        let mut filter = self.filter.clone();
+
+        if let Some(selector) = &self.selector {
+            if self.test_function.is_some() {
+                eyre::bail!("Cannot specify both a test selector and --test-function.");
+            }
+            selector.apply(&mut filter)?;
+        }
+
+        if let Some(test_function) = &self.test_function {
+            if filter.test_pattern.is_some() {
+                eyre::bail!(
+                    "Cannot specify both --test-function and --match-test. \
+                     Use --match-contract and --match-path to further limit the search instead."
+                );
+            }
+            filter.test_pattern = Some(test_function.clone());
+        }
+
+        Ok(filter.merge_with_config(config))
     }
 
     /// Returns whether `BuildArgs` was configured with `--watch`
@@ -543,12 +1396,178 @@ impl FlamegraphArgs {
     // }
 }
 
+/// Call tree of the test itself, with the runs it was built from.
+struct Execution {
+    tree: CallTree,
+    /// Which runs were profiled, shown as the subtitle.
+    runs: Option<String>,
+    /// Whether the tree is the average of several runs, which have no common execution order.
+    averaged: bool,
+    /// Whether the frames start where they ran, see [`BackendProfile::ordered`].
+    ordered: bool,
+}
+
+fn no_sampled_runs(input: &ProfileInput<'_>) -> eyre::Report {
+    eyre::eyre!(
+        "no runs of {} were sampled, is `gas_report_samples` set to 0?",
+        input.test_name
+    )
+}
+
+/// Returns the decoded arguments the test function was called with in a fuzz run.
+async fn fuzz_inputs(decoder: &CallTraceDecoder, run: &CallTraceArena) -> String {
+    let Some(node) = run.nodes().first() else {
+        return String::new();
+    };
+    match decoder.decode_function(&node.trace).await.func {
+        Some(call) => format!("({})", call.args.join(", ")),
+        None => node.trace.data.to_string(),
+    }
+}
+
+/// Creates the directory of the outputs at `stem`, name templates may contain directories.
+fn create_parent_dir(stem: &str) -> Result<()> {
+    if let Some(parent) = Path::new(stem).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Subcommands of `forge-flamegraph`.
+#[derive(Clone, Debug, clap::Subcommand)]
+pub enum FlamegraphSubcommand {
+    /// Render a profile saved with `--save-profile`, without running the test again.
+    Report(ReportArgs),
+    /// List the backends that can be selected with `--backend`.
+    Backends,
+}
+
+/// CLI arguments for `forge-flamegraph report`.
+#[derive(Clone, Debug, Parser)]
+pub struct ReportArgs {
+    /// Path of the saved profile.
+    #[arg(value_name = "PROFILE")]
+    profile: PathBuf,
+
+    /// Output formats to write.
+    #[arg(long, short = 'f', value_delimiter = ',', default_value = "svg")]
+    format: Vec<OutputFormat>,
+
+    /// Title of the flamegraph, defaults to the output file name.
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Width of the flamegraph in pixels.
+    #[arg(long)]
+    width: Option<usize>,
+
+    #[arg(long, short, help_heading = "Open flamegraph in default program")]
+    open: bool,
+}
+
+impl ReportArgs {
+    pub fn run(self) -> Result<()> {
+        let config = Self::load_flamegraph_config()?;
+        let mut profile = Profile::load(&self.profile)?;
+        // saved profiles keep the original names
+        config.transform_names(&mut profile.tree)?;
+
+        // `flamegraph_test_debug.profile.json` renders to `flamegraph_test_debug.svg`
+        let stem = self.profile.to_string_lossy();
+        let stem = stem.strip_suffix(".json").unwrap_or(&stem);
+        let stem = stem.strip_suffix(".profile").unwrap_or(stem);
+
+        let mut files = vec![];
+        for format in &self.format {
+            let mut options = config.svg_options()?;
+            if let Some(title) = &self.title {
+                options.title = title.clone();
+            }
+            if let Some(width) = self.width {
+                options.image_width = Some(width);
+            }
+            files.push(format::write_output(&profile, *format, stem, options)?);
+        }
+
+        println!(
+            "Rendered {}::{} ({} backend) to {}",
+            profile.meta.contract,
+            profile.meta.test,
+            profile.meta.backend,
+            files.join(", ")
+        );
+
+        if self.open {
+            if let Some(file) = files.first() {
+                open::that(file)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `flamegraph` section of the selected profile in `foundry.toml`, so that the
+    /// profile renders with the same name transforms and palette as when it was saved.
+    fn load_flamegraph_config() -> Result<FlamegraphConfig> {
+        let figment = Config::figment_with_root(find_project_root_path(None)?);
+        if !figment.contains("flamegraph") {
+            return Ok(FlamegraphConfig::default());
+        }
+        let config: FlamegraphConfig = figment.extract_inner("flamegraph")?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 impl Provider for FlamegraphArgs {
     fn metadata(&self) -> Metadata {
-        Metadata::named("Core Build Args Provider")
+        Metadata::named("Flamegraph Args Provider")
     }
 
-    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
-        Ok(Map::from([(Config::selected_profile(), Dict::default())]))
+    /// Returns the `flamegraph` section set by the flags, which override `foundry.toml`.
+    fn data(&self) -> Result<Map<figment::Profile, Dict>, figment::Error> {
+        let mut flamegraph = Dict::new();
+        if let Some(backend) = &self.backend {
+            flamegraph.insert("backend".to_string(), backend.as_str().into());
+        } else if self.debugtrace {
+            flamegraph.insert("backend".to_string(), "debug".into());
+        }
+        if self.merge {
+            flamegraph.insert("merge".to_string(), true.into());
+        }
+        if !self.format.is_empty() {
+            flamegraph.insert("formats".to_string(), Value::serialize(&self.format)?);
+        }
+        if !self.include.is_empty() {
+            flamegraph.insert("include".to_string(), Value::serialize(&self.include)?);
+        }
+        if let Some(fuzz) = &self.fuzz {
+            flamegraph.insert("fuzz".to_string(), Value::serialize(fuzz)?);
+        }
+        if let Some(invariant) = &self.invariant {
+            flamegraph.insert("invariant".to_string(), Value::serialize(invariant)?);
+        }
+        if let Some(weight) = &self.weight {
+            flamegraph.insert("weight".to_string(), Value::serialize(weight)?);
+        }
+        if let Some(name) = &self.name {
+            flamegraph.insert("name".to_string(), name.as_str().into());
+        }
+
+        let dict = Dict::from([("flamegraph".to_string(), flamegraph.into())]);
+        Ok(Map::from([(Config::selected_profile(), dict)]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_flags() {
+        // panics on flags clashing with the ones of forge, e.g. `-f` of `--fork-url`
+        FlamegraphArgs::command().debug_assert();
+        ReportArgs::command().debug_assert();
     }
 }
@@ -0,0 +1,206 @@
+use crate::{
+    flamegraph::Options, format::OutputFormat, output::NameTemplate, profile::Weight,
+    tree::CallTree,
+};
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use inferno::flamegraph::color::Palette;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, str::FromStr};
+
+/// Flamegraph settings, read from the `[profile.<name>.flamegraph]` section of `foundry.toml`
+/// and overridden by the command line flags.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlamegraphConfig {
+    /// Name of the backend to profile the tests with, see [`crate::backends::BackendRegistry`].
+    pub backend: String,
+    /// Whether to merge the stacks of unmerged debug profiles.
+    pub merge: bool,
+    /// Directory the outputs are written to, the current directory by default.
+    pub out_dir: Option<PathBuf>,
+    /// Name of the outputs of a test, see [`NameTemplate`].
+    pub name: Option<String>,
+    /// Width of the svg in pixels, inferno's default if unset.
+    pub image_width: Option<usize>,
+    /// Colour palette of the svg, one of inferno's palettes, e.g. `hot`, `mem` or `aqua`.
+    pub palette: Option<String>,
+    /// Omit frames narrower than this many pixels from the svg.
+    pub min_width: Option<f64>,
+    /// Regex replacements applied to every frame name, in order.
+    pub name_transforms: Vec<NameTransform>,
+    pub formats: Vec<OutputFormat>,
+    /// Parts of the test run profiled along with the test, as separate roots of its flamegraph.
+    pub include: Vec<Include>,
+    /// Fuzz runs to profile, the last run (or the counterexample) if unset.
+    pub fuzz: Option<FuzzMode>,
+    /// How invariant tests are profiled.
+    pub invariant: InvariantMode,
+    /// What the frames are measured in.
+    pub weight: Weight,
+}
+
+impl Default for FlamegraphConfig {
+    fn default() -> Self {
+        Self {
+            backend: "calltrace".to_string(),
+            merge: false,
+            out_dir: None,
+            name: None,
+            image_width: None,
+            palette: None,
+            min_width: None,
+            name_transforms: vec![],
+            formats: vec![OutputFormat::Svg],
+            include: vec![],
+            fuzz: None,
+            invariant: InvariantMode::Average,
+            weight: Weight::Gas,
+        }
+    }
+}
+
+/// Part of the test run before the test itself, in the order they run.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Include {
+    /// Deployment of the test contract, drawn as `[deploy <contract>]`.
+    Deployment,
+    /// The `setUp` function, drawn as `[setUp]`.
+    Setup,
+}
+
+/// Fuzz runs to profile, out of the runs sampled for the gas report, see the
+/// `gas_report_samples` fuzz setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FuzzMode {
+    /// Merge the runs into the average gas per run.
+    Average,
+    /// The run that used the most gas.
+    Worst,
+}
+
+/// How invariant tests are profiled, with every handler call as a child of the root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum InvariantMode {
+    /// Merge the runs sampled for the gas report into the average gas per run.
+    Average,
+    /// The failing sequence of calls, or the last run if the invariants held.
+    Sequence,
+}
+
+/// Replaces the matches of `pattern` in frame names with `replace`, which may refer to capture
+/// groups as `$1`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameTransform {
+    pub pattern: String,
+    pub replace: String,
+}
+
+impl FlamegraphConfig {
+    /// Checks the settings that can't be checked while deserializing. The backend is checked
+    /// against the registered backends when it is used.
+    pub fn validate(&self) -> Result<()> {
+        self.name_template()?;
+        self.svg_options()?;
+        self.name_transforms()?;
+        Ok(())
+    }
+
+    pub fn name_template(&self) -> Result<NameTemplate> {
+        self.name
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Returns the inferno options of the svg output.
+    pub fn svg_options(&self) -> Result<Options<'static>> {
+        let mut options = Options::default();
+        if let Some(image_width) = self.image_width {
+            options.image_width = Some(image_width);
+        }
+        if let Some(palette) = &self.palette {
+            options.colors = Palette::from_str(palette).map_err(|err| eyre::eyre!(err))?;
+        }
+        if let Some(min_width) = self.min_width {
+            options.min_width = min_width;
+        }
+        Ok(options)
+    }
+
+    fn name_transforms(&self) -> Result<Vec<(Regex, &str)>> {
+        self.name_transforms
+            .iter()
+            .map(|transform| {
+                let regex = Regex::new(&transform.pattern)
+                    .wrap_err_with(|| format!("invalid name transform `{}`", transform.pattern))?;
+                Ok((regex, transform.replace.as_str()))
+            })
+            .collect()
+    }
+
+    /// Renames the frames of the tree with the name transforms.
+    pub fn transform_names(&self, tree: &mut CallTree) -> Result<()> {
+        let transforms = self.name_transforms()?;
+        for frame in tree.frames_mut() {
+            for (regex, replace) in &transforms {
+                frame.name = regex.replace_all(&frame.name, *replace).into_owned();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{profile::FrameKind, tree::Frame};
+
+    #[test]
+    fn test_deserialize_partial() {
+        let config: FlamegraphConfig = serde_json::from_str(
+            r#"{
+                "backend": "debug",
+                "formats": ["svg", "chrome-trace"],
+                "include": ["setup"],
+                "name_transforms": [{ "pattern": "^([A-Za-z]+)Test\\.", "replace": "$1." }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.backend, "debug");
+        assert!(!config.merge);
+        assert_eq!(
+            config.formats,
+            vec![OutputFormat::Svg, OutputFormat::ChromeTrace]
+        );
+        assert_eq!(config.include, vec![Include::Setup]);
+        config.validate().unwrap();
+
+        let root = Frame::new("VaultTest.testDeposit()".to_string(), FrameKind::Call, 0);
+        let mut tree = CallTree::new(root);
+        config.transform_names(&mut tree).unwrap();
+        assert_eq!(tree.nodes()[0].frame.name, "Vault.testDeposit()");
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = FlamegraphConfig {
+            name: Some("{contract}_{line}".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = FlamegraphConfig {
+            palette: Some("nope".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
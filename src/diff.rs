@@ -0,0 +1,215 @@
+use eyre::{Result, WrapErr};
+use foundry_cli::utils::{CommandUtils, Git};
+use inferno::{
+    differential,
+    flamegraph::{self, Options},
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// A detached git worktree of the project at a given ref, removed on drop.
+pub struct GitWorktree {
+    dir: TempDir,
+    git_root: PathBuf,
+    project_root: PathBuf,
+}
+
+impl GitWorktree {
+    /// Checks out `rev` of the repository containing `root` in a temporary directory, along with
+    /// its submodules, e.g. `lib/forge-std`.
+    pub fn new(root: &Path, rev: &str) -> Result<Self> {
+        let git_root = Git::root_of(root)?;
+        let dir = tempfile::Builder::new()
+            .prefix("forge-flamegraph-")
+            .tempdir()?;
+
+        Git::new(&git_root)
+            .cmd()
+            .args(["worktree", "add", "--detach"])
+            .arg(dir.path())
+            .arg(rev)
+            .exec()?;
+
+        // dependencies are usually submodules, which worktrees don't check out
+        Git::new(dir.path())
+            .cmd()
+            .args(["submodule", "update", "--init", "--recursive"])
+            .exec()
+            .wrap_err_with(|| format!("failed to check out the submodules of `{rev}`"))?;
+
+        // the foundry project is not necessarily at the root of the repository
+        let project_root = dir
+            .path()
+            .join(root.strip_prefix(&git_root).unwrap_or(Path::new("")));
+
+        Ok(Self {
+            dir,
+            git_root,
+            project_root,
+        })
+    }
+
+    /// Returns the root of the foundry project inside the worktree.
+    pub fn project_root(&self) -> &Path {
+        &self.project_root
+    }
+}
+
+impl Drop for GitWorktree {
+    fn drop(&mut self) {
+        let _ = Git::new(&self.git_root)
+            .cmd()
+            .args(["worktree", "remove", "--force"])
+            .arg(self.dir.path())
+            .exec();
+    }
+}
+
+/// Renders a differential flamegraph of the `after` stacks, coloured by the gas delta from the
+/// `before` stacks: red frames got more expensive, blue frames got cheaper.
+pub fn write_differential<W: Write>(
+    before: &[String],
+    after: &[String],
+    options: &mut Options<'_>,
+    writer: W,
+) -> Result<()> {
+    let mut lines = vec![];
+    differential::from_readers(
+        differential::Options::default(),
+        before.join("\n").as_bytes(),
+        after.join("\n").as_bytes(),
+        &mut lines,
+    )?;
+
+    if options.count_name == flamegraph::defaults::COUNT_NAME {
+        options.count_name = "gas".to_string();
+    }
+    flamegraph::from_reader(options, lines.as_slice(), writer)?;
+    Ok(())
+}
+
+/// Change of the self gas of a function between two profiles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasChange {
+    pub function: String,
+    pub before: i64,
+    pub after: i64,
+}
+
+impl GasChange {
+    pub fn delta(&self) -> i64 {
+        self.after - self.before
+    }
+}
+
+/// Returns the self gas changes per function, the largest changes first.
+///
+/// The self gas of a function is the sum of the gas of the stacks it is the leaf of.
+pub fn self_gas_changes(before: &[String], after: &[String]) -> Vec<GasChange> {
+    let mut functions = BTreeMap::<&str, (i64, i64)>::new();
+    for (lines, is_before) in [(before, true), (after, false)] {
+        for (function, gas) in lines.iter().filter_map(|line| parse_leaf(line)) {
+            let entry = functions.entry(function).or_default();
+            if is_before {
+                entry.0 += gas;
+            } else {
+                entry.1 += gas;
+            }
+        }
+    }
+
+    let mut changes = functions
+        .into_iter()
+        .map(|(function, (before, after))| GasChange {
+            function: function.to_string(),
+            before,
+            after,
+        })
+        .filter(|change| change.delta() != 0)
+        .collect::<Vec<_>>();
+    changes.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    changes
+}
+
+/// Formats the `limit` largest self gas changes as a table.
+pub fn summary(changes: &[GasChange], limit: usize) -> String {
+    if changes.is_empty() {
+        return "No self gas changes.\n".to_string();
+    }
+
+    let mut summary = String::new();
+    writeln!(
+        summary,
+        "{:>12} {:>12} {:>12}  function",
+        "before", "after", "delta"
+    )
+    .unwrap();
+    for change in changes.iter().take(limit) {
+        writeln!(
+            summary,
+            "{:>12} {:>12} {:>+12}  {}",
+            change.before,
+            change.after,
+            change.delta(),
+            change.function
+        )
+        .unwrap();
+    }
+    if changes.len() > limit {
+        writeln!(summary, "... and {} more", changes.len() - limit).unwrap();
+    }
+    summary
+}
+
+/// Splits a folded stack line into its leaf frame and gas.
+fn parse_leaf(line: &str) -> Option<(&str, i64)> {
+    let (stack, gas) = line.rsplit_once(' ')?;
+    let leaf = stack.rsplit(';').next()?;
+    Some((leaf, gas.parse().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_self_gas_changes() {
+        let before = vec![
+            "Vault.deposit 100".to_string(),
+            "Vault.deposit;Token.transferFrom 500".to_string(),
+            "Vault.deposit;Vault._mint 50".to_string(),
+        ];
+        let after = vec![
+            "Vault.deposit 120".to_string(),
+            "Vault.deposit;Token.transferFrom 300".to_string(),
+            "Vault.deposit;Vault._mint 50".to_string(),
+        ];
+        let changes = self_gas_changes(&before, &after);
+        assert_eq!(
+            changes,
+            vec![
+                GasChange {
+                    function: "Token.transferFrom".to_string(),
+                    before: 500,
+                    after: 300,
+                },
+                GasChange {
+                    function: "Vault.deposit".to_string(),
+                    before: 100,
+                    after: 120,
+                },
+            ]
+        );
+        assert!(summary(&changes, 1).contains("... and 1 more"));
+    }
+}
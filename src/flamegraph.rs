@@ -1,6 +1,22 @@
-use std::{fs, io::Read, path::Path};
+use crate::tree::CallTree;
+use eyre::Result;
+use std::{
+    fs,
+    io::{BufWriter, Write},
+};
 
-pub use inferno::flamegraph::{self, Options};
+pub use inferno::flamegraph::{
+    self,
+    color::{Color, PaletteMap},
+    Options,
+};
+
+/// Colour of the frames that reverted.
+pub const REVERT_COLOR: Color = Color {
+    r: 160,
+    g: 90,
+    b: 220,
+};
 
 pub struct Flamegraph<'a> {
     pub folded_stack_lines: Vec<String>,
@@ -8,30 +24,133 @@ pub struct Flamegraph<'a> {
 }
 
 impl<'a> Flamegraph<'a> {
-    pub fn generate(&mut self, file_name: &String, merge_stacks: bool) {
-        if Path::new(&file_name).exists() {
-            fs::remove_file(file_name).unwrap();
+    pub fn from_call_tree(tree: &CallTree) -> Self {
+        Self {
+            folded_stack_lines: tree.folded_stack_lines(),
+            options: Options::default(),
         }
+    }
+
+    /// Renders the flamegraph as svg to `writer`, with the frames measured in gas.
+    ///
+    /// Unmerged stacks are drawn as a flame chart, in execution order. The options are left as
+    /// they are, a count name set by the caller is kept.
+    pub fn write<W: Write>(&mut self, merge_stacks: bool, writer: W) -> Result<()> {
+        self.render(None, merge_stacks, writer)
+    }
+
+    /// Renders the flamegraph to the svg file `file_name`, titled after the file unless the
+    /// caller set a title.
+    pub fn generate(&mut self, file_name: &str, merge_stacks: bool) -> Result<()> {
+        let file = BufWriter::new(fs::File::create(file_name)?);
+        self.render(Some(file_name), merge_stacks, file)
+    }
 
-        self.options.title = file_name.clone();
+    fn render<W: Write>(
+        &mut self,
+        title: Option<&str>,
+        merge_stacks: bool,
+        mut writer: W,
+    ) -> Result<()> {
+        let options = &mut self.options;
+        let original = (
+            options.title.clone(),
+            options.count_name.clone(),
+            options.flame_chart,
+        );
+        if let Some(title) = title {
+            if options.title == flamegraph::defaults::TITLE {
+                options.title = title.to_string();
+            }
+        }
+        if options.count_name == flamegraph::defaults::COUNT_NAME {
+            options.count_name = "gas".to_string();
+        }
         if !merge_stacks {
-            self.options.flame_chart = true;
+            options.flame_chart = true;
+        }
+
+        let result = flamegraph::from_lines(
+            options,
+            self.folded_stack_lines.iter().map(String::as_str),
+            &mut writer,
+        );
+        (options.title, options.count_name, options.flame_chart) = original;
+        result?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns a palette map colouring the frames of the tree that reverted, `None` if none did.
+    ///
+    /// Frames are coloured by name, reverted frames are told apart by their revert reason, see
+    /// [`Frame::label`](crate::tree::Frame::label).
+    pub fn revert_palette(tree: &CallTree) -> Option<PaletteMap> {
+        let mut palette = PaletteMap::default();
+        for node in tree.nodes() {
+            if node.frame.revert.is_some() {
+                palette.insert(node.frame.label(), REVERT_COLOR);
+            }
         }
+        let reverted = palette.iter().next().is_some();
+        reverted.then_some(palette)
+    }
+
+    /// Returns the sum of the gas of all the folded stack lines.
+    pub fn total_gas(&self) -> i64 {
+        self.folded_stack_lines
+            .iter()
+            .filter_map(|line| line.rsplit_once(' '))
+            .filter_map(|(_, gas)| gas.parse::<i64>().ok())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{profile::FrameKind, tree::Frame};
+
+    #[test]
+    fn test_write() {
+        let mut flamegraph = Flamegraph {
+            folded_stack_lines: vec!["Vault.deposit;samples 100".to_string()],
+            options: Options::default(),
+        };
+        flamegraph.options.title = "deposit".to_string();
+
+        let mut svg = vec![];
+        flamegraph.write(false, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("<title>samples (100 gas, 100.00%)</title>"));
+        assert!(svg.contains(">deposit<"));
+
+        // the options of the caller are untouched
+        assert_eq!(flamegraph.options.title, "deposit");
+        assert_eq!(
+            flamegraph.options.count_name,
+            flamegraph::defaults::COUNT_NAME
+        );
+        assert!(!flamegraph.options.flame_chart);
+    }
+
+    #[test]
+    fn test_revert_palette() {
+        let mut tree = CallTree::new(Frame {
+            gas_end: 100,
+            ..Frame::new("Vault.withdraw".to_string(), FrameKind::Call, 0)
+        });
+        assert!(Flamegraph::revert_palette(&tree).is_none());
+
+        tree.frame_mut(tree.root()).revert = Some("insufficient balance".to_string());
+        let mut palette = Flamegraph::revert_palette(&tree).unwrap();
+        let mut flamegraph = Flamegraph::from_call_tree(&tree);
+        flamegraph.options.palette_map = Some(&mut palette);
 
-        let file = fs::File::create(file_name).unwrap();
-
-        flamegraph::from_lines(
-            &mut self.options,
-            self.folded_stack_lines.iter().map(|s| s.as_str()),
-            file,
-        )
-        .unwrap();
-
-        let mut buf = String::new();
-        let mut file = fs::File::open(file_name).unwrap();
-        file.read_to_string(&mut buf)
-            .expect("failed to read flamegraph file");
-        let buf = buf.replace("samples", "gas");
-        fs::write(file_name, buf).expect("failed to write flamegraph file");
+        let mut svg = vec![];
+        flamegraph.write(true, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("Vault.withdraw [reverted: insufficient balance]"));
+        assert!(svg.contains("rgb(160,90,220)"));
     }
 }
@@ -0,0 +1,161 @@
+//! Writer for the [callgrind](https://valgrind.org/docs/manual/cl-format.html) format, as opened
+//! by KCachegrind and QCachegrind.
+
+use crate::{profile::Profile, tree::NodeId};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
+
+const UNKNOWN_FILE: &str = "???";
+
+/// Writes the profile in the callgrind format.
+///
+/// The self gas of every frame is attributed to the solidity lines it was spent on, and calls
+/// between frames are written with their inclusive gas, so that the annotated source view of
+/// KCachegrind shows gas per line.
+pub fn write<W: Write>(profile: &Profile, mut writer: W) -> eyre::Result<()> {
+    writeln!(writer, "# callgrind format")?;
+    writeln!(writer, "version: 1")?;
+    writeln!(writer, "creator: forge-flamegraph")?;
+    writeln!(
+        writer,
+        "cmd: {}::{}",
+        profile.meta.contract, profile.meta.test
+    )?;
+    writeln!(writer, "positions: line")?;
+    writeln!(writer, "events: Gas")?;
+    writeln!(writer)?;
+
+    let mut names = Names::default();
+    let root = profile.tree.root();
+    write_node(profile, root, &mut names, &mut writer)?;
+
+    writeln!(writer, "totals: {}", profile.tree.total_gas(root).max(0))?;
+    Ok(())
+}
+
+/// Compressed names of files and functions, written in full only the first time they appear.
+#[derive(Default)]
+struct Names {
+    files: HashMap<String, usize>,
+    functions: HashMap<String, usize>,
+}
+
+impl Names {
+    fn file(&mut self, name: &str) -> String {
+        compress(&mut self.files, name)
+    }
+
+    fn function(&mut self, name: &str) -> String {
+        compress(&mut self.functions, name)
+    }
+}
+
+fn compress(ids: &mut HashMap<String, usize>, name: &str) -> String {
+    if let Some(id) = ids.get(name) {
+        return format!("({id})");
+    }
+    let id = ids.len() + 1;
+    ids.insert(name.to_string(), id);
+    format!("({id}) {name}")
+}
+
+fn file_name(profile: &Profile, index: Option<u32>) -> String {
+    index
+        .and_then(|index| profile.sources.get(&index))
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| UNKNOWN_FILE.to_string())
+}
+
+fn write_node<W: Write>(
+    profile: &Profile,
+    id: NodeId,
+    names: &mut Names,
+    writer: &mut W,
+) -> eyre::Result<()> {
+    let tree = &profile.tree;
+    let node = tree.node(id);
+    let frame = &node.frame;
+    let file = file_name(profile, frame.source.and_then(|source| source.index));
+    let line = frame.source.and_then(|source| source.line).unwrap_or(0);
+
+    writeln!(writer, "fl={}", names.file(&file))?;
+    writeln!(writer, "fn={}", names.function(&frame.name))?;
+
+    // self gas by file and line, gas not mapped to a range goes to the line of the frame
+    let mut costs: BTreeMap<(String, usize), u64> = BTreeMap::new();
+    let mut mapped = 0;
+    for cost in &frame.costs {
+        let file = file_name(profile, cost.source.index);
+        *costs
+            .entry((file, cost.source.line.unwrap_or(0)))
+            .or_default() += cost.gas;
+        mapped += cost.gas;
+    }
+    let unmapped = (tree.self_gas(id) as u64).saturating_sub(mapped);
+    if unmapped > 0 {
+        *costs.entry((file.clone(), line)).or_default() += unmapped;
+    }
+
+    let mut current_file = file.clone();
+    for ((cost_file, cost_line), gas) in costs {
+        if cost_file != current_file {
+            writeln!(writer, "fi={}", names.file(&cost_file))?;
+            current_file = cost_file;
+        }
+        writeln!(writer, "{cost_line} {gas}")?;
+    }
+    if current_file != file {
+        writeln!(writer, "fe={}", names.file(&file))?;
+    }
+
+    // calls are made from the line of the frame, the exact call site is not known
+    for child in tree.children(id) {
+        let source = child.frame.source;
+        let child_file = file_name(profile, source.and_then(|source| source.index));
+        let child_line = source.and_then(|source| source.line).unwrap_or(0);
+        writeln!(writer, "cfi={}", names.file(&child_file))?;
+        writeln!(writer, "cfn={}", names.function(&child.frame.name))?;
+        writeln!(writer, "calls=1 {child_line}")?;
+        writeln!(writer, "{line} {}", tree.total_gas(child.id).max(0))?;
+    }
+    writeln!(writer)?;
+
+    for child in &node.children {
+        write_node(profile, *child, names, writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::profile::fixture;
+
+    #[test]
+    fn test_write_callgrind() {
+        let mut out = vec![];
+        write(&fixture::profile(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let body = out.split("\n\n").skip(1).collect::<Vec<_>>().join("\n\n");
+        assert_eq!(
+            body,
+            "fl=(1) src/Vault.sol\n\
+             fn=(1) Vault.deposit\n\
+             3 5\n\
+             4 50\n\
+             5 15\n\
+             cfi=(1)\n\
+             cfn=(2) _mint\n\
+             calls=1 9\n\
+             3 30\n\
+             \n\
+             fl=(1)\n\
+             fn=(2)\n\
+             9 30\n\
+             \n\
+             totals: 100\n"
+        );
+    }
+}
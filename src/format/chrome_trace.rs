@@ -0,0 +1,189 @@
+//! Writer for the Chrome trace event format, as opened by Perfetto and `chrome://tracing`.
+//!
+//! See <https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU>.
+
+use crate::{
+    profile::{FrameKind, Profile},
+    tree::NodeId,
+};
+use alloy_primitives::Address;
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace {
+    trace_events: Vec<TraceEvent>,
+    /// Timestamps are gas, the unit only affects how they are displayed.
+    display_time_unit: &'static str,
+    other_data: BTreeMap<&'static str, String>,
+}
+
+/// A complete ("X") event.
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: FrameKind,
+    ph: &'static str,
+    ts: u64,
+    dur: u64,
+    pid: u32,
+    tid: u32,
+    args: Args,
+}
+
+#[derive(Serialize)]
+struct Args {
+    kind: FrameKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<Address>,
+    /// `file:line` of the frame in the solidity sources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<usize>,
+    self_gas: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revert: Option<String>,
+}
+
+/// Writes the profile as a Chrome trace, with one complete event per frame spanning its gas
+/// range.
+pub fn write<W: Write>(profile: &Profile, writer: W) -> eyre::Result<()> {
+    let mut trace_events = vec![];
+    let tree = &profile.tree;
+    let root = &tree.node(tree.root()).frame;
+    push_events(
+        profile,
+        tree.root(),
+        (root.gas_start, root.gas_end.max(root.gas_start)),
+        &mut trace_events,
+    );
+
+    let trace = Trace {
+        trace_events,
+        display_time_unit: "ns",
+        other_data: BTreeMap::from([
+            ("contract", profile.meta.contract.clone()),
+            ("test", profile.meta.test.clone()),
+            ("backend", profile.meta.backend.clone()),
+            ("unit", profile.meta.weight.unit().to_string()),
+        ]),
+    };
+    serde_json::to_writer(writer, &trace)?;
+    Ok(())
+}
+
+/// Pushes the event of the node `id` and its children, clamped to the range of the parent so
+/// that events are properly nested.
+fn push_events(
+    profile: &Profile,
+    id: NodeId,
+    parent_range: (u64, u64),
+    events: &mut Vec<TraceEvent>,
+) {
+    let node = profile.tree.node(id);
+    let frame = &node.frame;
+    let start = frame.gas_start.clamp(parent_range.0, parent_range.1);
+    let end = frame.gas_end.clamp(start, parent_range.1);
+
+    let source = frame.source;
+    events.push(TraceEvent {
+        name: frame.name.clone(),
+        cat: frame.kind,
+        ph: "X",
+        ts: start,
+        dur: end - start,
+        pid: 1,
+        tid: 1,
+        args: Args {
+            kind: frame.kind,
+            address: frame.address,
+            location: source.and_then(|source| {
+                let path = profile.sources.get(&source.index?)?.display();
+                Some(match source.line {
+                    Some(line) => format!("{path}:{line}"),
+                    None => path.to_string(),
+                })
+            }),
+            offset: source.map(|source| source.offset),
+            length: source.map(|source| source.length),
+            self_gas: profile.tree.self_gas(id),
+            revert: frame.revert.clone(),
+        },
+    });
+
+    for child in &node.children {
+        push_events(profile, *child, (start, end), events);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{profile::fixture, tree::Frame};
+
+    #[test]
+    fn test_write_chrome_trace() {
+        let mut profile = fixture::profile();
+        let tree = &mut profile.tree;
+        let mint = tree.nodes()[1].id;
+        tree.frame_mut(mint).revert = Some("cap exceeded".to_string());
+        // a frame that did not return before its parent, e.g. out of gas
+        tree.push(
+            mint,
+            Frame {
+                gas_end: 85,
+                address: Some(Address::repeat_byte(0x11)),
+                ..Frame::new("Token.mint".to_string(), FrameKind::Call, 60)
+            },
+        );
+
+        let mut buf = vec![];
+        write(&profile, &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["otherData"]["unit"], "gas");
+
+        let events = json["traceEvents"].as_array().unwrap();
+        let spans = events
+            .iter()
+            .map(|event| {
+                let name = event["name"].as_str().unwrap();
+                (
+                    name,
+                    event["ts"].as_u64().unwrap(),
+                    event["dur"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // the call is clamped to the range of `_mint`
+        assert_eq!(
+            spans,
+            vec![
+                ("Vault.deposit", 0, 100),
+                ("_mint", 40, 30),
+                ("Token.mint", 60, 10)
+            ]
+        );
+
+        let args = &events[0]["args"];
+        assert_eq!(args["kind"], "call");
+        assert_eq!(args["location"], "src/Vault.sol:3");
+        assert_eq!(args["self_gas"], 70);
+        assert!(args.get("revert").is_none());
+
+        let args = &events[1]["args"];
+        assert_eq!(args["kind"], "internal");
+        assert_eq!(args["location"], "src/Vault.sol:9");
+        assert_eq!(args["revert"], "cap exceeded");
+
+        let args = &events[2]["args"];
+        assert_eq!(
+            args["address"],
+            "0x1111111111111111111111111111111111111111"
+        );
+        assert!(args.get("location").is_none());
+    }
+}
@@ -0,0 +1,378 @@
+//! Standalone html report showing the gas spent on every line of the solidity sources, like a
+//! coverage report but for gas.
+
+use crate::{
+    index::escape,
+    profile::{FrameKind, Profile},
+    tree::CallTree,
+};
+use std::{collections::BTreeMap, fmt::Write as _, fs, io::Write, path::Path};
+
+/// Number of expressions listed per file.
+const TOP_EXPRESSIONS: usize = 20;
+
+/// Reads the source files of the profile, relative to the project root it was taken in.
+///
+/// Files that can't be read are skipped.
+pub fn read_sources(profile: &Profile) -> BTreeMap<u32, String> {
+    let root = profile.meta.root.as_deref().unwrap_or(Path::new(""));
+    profile
+        .sources
+        .iter()
+        .filter_map(|(id, path)| Some((*id, fs::read_to_string(root.join(path)).ok()?)))
+        .collect()
+}
+
+/// Gas totals collected from the frames of a profile.
+#[derive(Default)]
+struct Heatmap {
+    /// Gas by source id and line.
+    lines: BTreeMap<(u32, usize), u64>,
+    /// Gas and line by source id, offset and length.
+    expressions: BTreeMap<(u32, usize, usize), (usize, u64)>,
+    functions: BTreeMap<String, FunctionStats>,
+}
+
+struct FunctionStats {
+    /// Source id and line of the function, if known.
+    location: Option<(u32, usize)>,
+    calls: u64,
+    /// Gas including the functions it calls.
+    gas: i64,
+}
+
+impl Heatmap {
+    fn collect(&mut self, tree: &CallTree) {
+        for node in tree.nodes() {
+            let frame = &node.frame;
+            if frame.kind != FrameKind::Opcode {
+                let location = frame
+                    .source
+                    .and_then(|source| Some((source.index?, source.line?)));
+                let stats = self
+                    .functions
+                    .entry(frame.name.clone())
+                    .or_insert(FunctionStats {
+                        location,
+                        calls: 0,
+                        gas: 0,
+                    });
+                stats.calls += 1;
+                stats.gas += tree.total_gas(node.id).max(0);
+            }
+
+            // frames that weren't stepped through spend their gas at their own location
+            let costs = if frame.costs.is_empty() {
+                frame
+                    .source
+                    .map(|source| vec![(source, tree.self_gas(node.id) as u64)])
+                    .unwrap_or_default()
+            } else {
+                frame
+                    .costs
+                    .iter()
+                    .map(|cost| (cost.source, cost.gas))
+                    .collect()
+            };
+            for (source, gas) in costs {
+                let (Some(index), Some(line)) = (source.index, source.line) else {
+                    continue;
+                };
+                if gas == 0 {
+                    continue;
+                }
+                *self.lines.entry((index, line)).or_default() += gas;
+                self.expressions
+                    .entry((index, source.offset, source.length))
+                    .or_insert((line, 0))
+                    .1 += gas;
+            }
+        }
+    }
+}
+
+/// Writes the heatmap of the profile as html.
+///
+/// `sources` are the contents of the source files by source id, see [`read_sources`]. Functions
+/// link to their frames in `flamegraph`, the svg rendered from the same profile.
+pub fn write<W: Write>(
+    profile: &Profile,
+    sources: &BTreeMap<u32, String>,
+    flamegraph: &str,
+    mut writer: W,
+) -> eyre::Result<()> {
+    let mut heatmap = Heatmap::default();
+    heatmap.collect(&profile.tree);
+    writer.write_all(render(profile, sources, flamegraph, &heatmap).as_bytes())?;
+    Ok(())
+}
+
+fn render(
+    profile: &Profile,
+    sources: &BTreeMap<u32, String>,
+    flamegraph: &str,
+    heatmap: &Heatmap,
+) -> String {
+    let mut functions = heatmap.functions.iter().collect::<Vec<_>>();
+    functions.sort_by(|a, b| b.1.gas.cmp(&a.1.gas).then_with(|| a.0.cmp(b.0)));
+
+    let mut function_rows = String::new();
+    for (name, stats) in functions {
+        let location = match stats.location {
+            Some((index, line)) => format!(
+                "<a href=\"#{}\">{}:{line}</a>",
+                anchor(index, line),
+                escape(&file_name(profile, index))
+            ),
+            None => String::new(),
+        };
+        writeln!(
+            function_rows,
+            "<tr><td><a href=\"{}\">{}</a></td><td>{location}</td><td class=\"gas\">{}</td><td class=\"gas\">{}</td></tr>",
+            escape(&frame_link(flamegraph, name)),
+            escape(name),
+            stats.calls,
+            stats.gas
+        )
+        .unwrap();
+    }
+
+    // files with the most gas first
+    let mut file_gas: BTreeMap<u32, u64> = BTreeMap::new();
+    for ((index, _), gas) in &heatmap.lines {
+        *file_gas.entry(*index).or_default() += gas;
+    }
+    let mut files = file_gas.into_iter().collect::<Vec<_>>();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let max_line_gas = heatmap.lines.values().copied().max().unwrap_or(0);
+    let mut files_html = String::new();
+    for (index, gas) in files {
+        let name = file_name(profile, index);
+        writeln!(
+            files_html,
+            "<h2>{} <span class=\"total\">{gas} gas</span></h2>",
+            escape(&name)
+        )
+        .unwrap();
+        let Some(source) = sources.get(&index) else {
+            writeln!(files_html, "<p class=\"missing\">source not found</p>").unwrap();
+            continue;
+        };
+
+        // functions defined on each line, linked to the flamegraph
+        let mut line_functions: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for (function, stats) in &heatmap.functions {
+            if let Some((function_index, line)) = stats.location {
+                if function_index == index {
+                    line_functions.entry(line).or_default().push(function);
+                }
+            }
+        }
+
+        // expressions by the line they start on
+        let mut expressions = heatmap
+            .expressions
+            .iter()
+            .filter(|((expression_index, ..), _)| *expression_index == index)
+            .map(|((_, offset, length), (line, gas))| {
+                (*line, *gas, snippet(source, *offset, *length))
+            })
+            .collect::<Vec<_>>();
+        expressions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        writeln!(files_html, "<table class=\"source\">").unwrap();
+        for (i, code) in source.lines().enumerate() {
+            let line = i + 1;
+            let gas = heatmap.lines.get(&(index, line)).copied().unwrap_or(0);
+            let title = expressions
+                .iter()
+                .filter(|(expression_line, ..)| *expression_line == line)
+                .map(|(_, gas, snippet)| format!("{gas} gas: {snippet}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let links = line_functions
+                .get(&line)
+                .into_iter()
+                .flatten()
+                .map(|function| {
+                    format!(
+                        " <a class=\"frame\" href=\"{}\">{}</a>",
+                        escape(&frame_link(flamegraph, function)),
+                        escape(function)
+                    )
+                })
+                .collect::<String>();
+            writeln!(
+                files_html,
+                "<tr id=\"{}\" style=\"{}\" title=\"{}\"><td class=\"gas\">{}</td><td class=\"line\">{line}</td><td><pre>{}</pre></td><td>{links}</td></tr>",
+                anchor(index, line),
+                heat(gas, max_line_gas),
+                escape(&title),
+                if gas > 0 { gas.to_string() } else { String::new() },
+                escape(code)
+            )
+            .unwrap();
+        }
+        writeln!(files_html, "</table>").unwrap();
+
+        writeln!(files_html, "<h3>Hottest expressions</h3>\n<table>").unwrap();
+        for (line, gas, snippet) in expressions.iter().take(TOP_EXPRESSIONS) {
+            writeln!(
+                files_html,
+                "<tr><td class=\"gas\">{gas}</td><td><a href=\"#{}\">{line}</a></td><td><code>{}</code></td></tr>",
+                anchor(index, *line),
+                escape(snippet)
+            )
+            .unwrap();
+        }
+        writeln!(files_html, "</table>").unwrap();
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: monospace; }}
+table {{ border-collapse: collapse; }}
+td, th {{ padding: 0 12px; text-align: left; vertical-align: top; }}
+pre {{ margin: 0; }}
+.gas, .line {{ text-align: right; }}
+.line {{ color: #888; }}
+.total {{ color: #888; font-size: 0.7em; }}
+.frame {{ font-size: 0.8em; }}
+.missing {{ color: #c00; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>{total} gas, see <a href="{flamegraph}">{flamegraph}</a></p>
+<table>
+<tr><th>Function</th><th>Location</th><th class="gas">Calls</th><th class="gas">Gas</th></tr>
+{function_rows}</table>
+{files_html}</body>
+</html>
+"#,
+        title = escape(&format!("{}::{}", profile.meta.contract, profile.meta.test)),
+        total = profile.tree.total_gas(profile.tree.root()),
+        flamegraph = escape(flamegraph),
+    )
+}
+
+fn file_name(profile: &Profile, index: u32) -> String {
+    profile
+        .sources
+        .get(&index)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| format!("source {index}"))
+}
+
+fn anchor(index: u32, line: usize) -> String {
+    format!("L{index}-{line}")
+}
+
+/// Background of a line, from transparent to red as its gas gets closer to `max`.
+fn heat(gas: u64, max: u64) -> String {
+    if gas == 0 || max == 0 {
+        return String::new();
+    }
+    let ratio = gas as f64 / max as f64;
+    format!("background: rgba(255, 0, 0, {:.2})", 0.05 + 0.6 * ratio)
+}
+
+/// Returns the source of an expression on a single line, shortened if too long.
+fn snippet(source: &str, offset: usize, length: usize) -> String {
+    let code = source.get(offset..offset + length).unwrap_or_default();
+    let code = code.split_whitespace().collect::<Vec<_>>().join(" ");
+    if code.chars().count() > 80 {
+        format!("{}…", code.chars().take(80).collect::<String>())
+    } else {
+        code
+    }
+}
+
+/// Returns a link to the flamegraph that highlights the frames of `function`, using the search
+/// of the inferno svg.
+fn frame_link(flamegraph: &str, function: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in function.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push(' ');
+
+    let mut query = String::new();
+    for byte in pattern.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            query.push(byte as char);
+        } else {
+            write!(query, "%{byte:02X}").unwrap();
+        }
+    }
+    format!("{flamegraph}?s={query}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        profile::{SourceCost, SourceLocation},
+        tree::Frame,
+    };
+
+    fn location(offset: usize, length: usize, line: usize) -> SourceLocation {
+        SourceLocation {
+            index: Some(0),
+            offset,
+            length,
+            line: Some(line),
+        }
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut tree = CallTree::new(Frame {
+            gas_end: 100,
+            source: Some(location(0, 10, 1)),
+            costs: vec![
+                SourceCost {
+                    source: location(20, 5, 2),
+                    gas: 50,
+                },
+                SourceCost {
+                    source: location(26, 3, 2),
+                    gas: 20,
+                },
+            ],
+            ..Frame::new("Vault.deposit".to_string(), FrameKind::Call, 0)
+        });
+        tree.push(
+            tree.root(),
+            Frame {
+                gas_end: 70,
+                source: Some(location(40, 8, 4)),
+                ..Frame::new("_mint".to_string(), FrameKind::Internal, 40)
+            },
+        );
+
+        let mut heatmap = Heatmap::default();
+        heatmap.collect(&tree);
+        assert_eq!(heatmap.lines, BTreeMap::from([((0, 2), 70), ((0, 4), 30)]));
+        assert_eq!(heatmap.expressions[&(0, 20, 5)], (2, 50));
+        assert_eq!(heatmap.functions["_mint"].gas, 30);
+        assert_eq!(heatmap.functions["Vault.deposit"].location, Some((0, 1)));
+    }
+
+    #[test]
+    fn test_frame_link() {
+        assert_eq!(
+            frame_link("flamegraph.svg", "Vault.deposit"),
+            "flamegraph.svg?s=%5EVault%5C.deposit%20"
+        );
+    }
+}
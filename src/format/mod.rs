@@ -0,0 +1,117 @@
+use crate::{
+    flamegraph::{flamegraph::defaults::COUNT_NAME, Flamegraph, Options},
+    profile::{Profile, Weight},
+};
+use clap::ValueEnum;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::BufWriter, path::Path};
+
+pub mod callgrind;
+pub mod chrome_trace;
+pub mod heatmap;
+pub mod pprof;
+pub mod speedscope;
+
+/// Output formats a profile can be rendered to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Interactive flamegraph svg.
+    Svg,
+    /// Folded stack lines, as consumed by inferno and other flamegraph tools.
+    Folded,
+    /// Speedscope json, see <https://www.speedscope.app>.
+    Speedscope,
+    /// Chrome trace event json, for Perfetto and `chrome://tracing`.
+    ChromeTrace,
+    /// Gzipped pprof `profile.proto`, for `go tool pprof`.
+    Pprof,
+    /// Callgrind profile with gas per solidity line, for KCachegrind.
+    Callgrind,
+    /// Html report of the gas spent on every solidity line.
+    Heatmap,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Svg => "svg",
+            OutputFormat::Folded => "folded",
+            OutputFormat::Speedscope => "speedscope.json",
+            OutputFormat::ChromeTrace => "trace.json",
+            OutputFormat::Pprof => "pb.gz",
+            OutputFormat::Callgrind => "callgrind",
+            OutputFormat::Heatmap => "heatmap.html",
+        }
+    }
+}
+
+/// Renders the profile to `{stem}.{extension}` and returns the file name.
+///
+/// `options` only apply to the svg output.
+pub fn write_output(
+    profile: &Profile,
+    format: OutputFormat,
+    stem: &str,
+    options: Options<'_>,
+) -> Result<String> {
+    let file_name = format!("{stem}.{}", format.extension());
+    if matches!(format, OutputFormat::Callgrind | OutputFormat::Heatmap) {
+        // the gas of the solidity lines is not counted in instructions
+        eyre::ensure!(
+            profile.meta.weight == Weight::Gas,
+            "the {format:?} output is measured in gas, it can't be weighted by {}",
+            profile.meta.weight.unit()
+        );
+    }
+    match format {
+        OutputFormat::Svg => {
+            let mut revert_palette = Flamegraph::revert_palette(&profile.tree);
+            let mut flamegraph = Flamegraph::from_call_tree(&profile.tree);
+            flamegraph.options = options;
+            // measured in gas unless the caller named the count
+            if profile.meta.weight != Weight::Gas && flamegraph.options.count_name == COUNT_NAME {
+                flamegraph.options.count_name = profile.meta.weight.unit().to_string();
+            }
+            if flamegraph.options.palette_map.is_none() {
+                flamegraph.options.palette_map = revert_palette.as_mut();
+            }
+            if flamegraph.options.subtitle.is_none() {
+                flamegraph.options.subtitle = profile.meta.subtitle.clone();
+            }
+            flamegraph.generate(&file_name, profile.meta.merge_stacks)?;
+        }
+        OutputFormat::Folded => {
+            let mut lines = profile.tree.folded_stack_lines().join("\n");
+            lines.push('\n');
+            fs::write(&file_name, lines)?;
+        }
+        OutputFormat::Speedscope => {
+            speedscope::write(profile, BufWriter::new(fs::File::create(&file_name)?))?;
+        }
+        OutputFormat::ChromeTrace => {
+            chrome_trace::write(profile, BufWriter::new(fs::File::create(&file_name)?))?;
+        }
+        OutputFormat::Pprof => {
+            pprof::write(profile, BufWriter::new(fs::File::create(&file_name)?))?;
+        }
+        OutputFormat::Callgrind => {
+            callgrind::write(profile, BufWriter::new(fs::File::create(&file_name)?))?;
+        }
+        OutputFormat::Heatmap => {
+            // functions link to the svg written next to the heatmap
+            let flamegraph = Path::new(stem)
+                .file_name()
+                .map(|stem| format!("{}.svg", stem.to_string_lossy()))
+                .unwrap_or_default();
+            heatmap::write(
+                profile,
+                &heatmap::read_sources(profile),
+                &flamegraph,
+                BufWriter::new(fs::File::create(&file_name)?),
+            )?;
+        }
+    }
+    Ok(file_name)
+}
@@ -0,0 +1,329 @@
+//! Writer for the gzipped [pprof](https://github.com/google/pprof) `profile.proto` format.
+//!
+//! The schema is small enough that messages are encoded by hand rather than generated.
+
+use crate::{
+    profile::{Profile, Weight},
+    tree::NodeId,
+};
+use flate2::{write::GzEncoder, Compression};
+use std::{collections::HashMap, io::Write};
+
+/// Writes the profile as a gzipped pprof profile.
+///
+/// Every frame becomes a sample valued with its self gas and, if the backend counts them, its
+/// instructions. Profiles weighted by instructions or time only have that value. Functions carry
+/// the solidity file and line of debug frames, and the self gas of a debug frame is split into a
+/// sample per line it was spent on, so that `pprof -list` shows gas per line.
+pub fn write<W: Write>(profile: &Profile, writer: W) -> eyre::Result<()> {
+    let root = profile.tree.root();
+    let mut builder = Builder {
+        weight: profile.meta.weight,
+        with_instructions: profile.tree.node(root).frame.instructions.is_some()
+            && profile.meta.weight == Weight::Gas,
+        ..Default::default()
+    };
+    builder.push_samples(profile, root, &mut vec![]);
+
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    encoder.write_all(&builder.encode())?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Builder {
+    /// What the first value of the samples measures.
+    weight: Weight,
+    with_instructions: bool,
+    strings: Vec<String>,
+    string_ids: HashMap<String, i64>,
+    /// (name, filename, start line) of each function, the id of a function is its index + 1.
+    functions: Vec<(i64, i64, i64)>,
+    function_ids: HashMap<(i64, i64), u64>,
+    /// (function id, line) of each location, the id of a location is its index + 1.
+    locations: Vec<(u64, i64)>,
+    location_ids: HashMap<(u64, i64), u64>,
+    samples: Vec<(Vec<u64>, Vec<i64>)>,
+}
+
+impl Builder {
+    fn string(&mut self, s: &str) -> i64 {
+        if self.strings.is_empty() {
+            // the first entry of the string table must be the empty string
+            self.strings.push(String::new());
+            self.string_ids.insert(String::new(), 0);
+        }
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as i64;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    /// Returns the id of the function `name` defined in `file`, starting at `start_line`.
+    fn function(&mut self, name: &str, file: &str, start_line: i64) -> u64 {
+        let key = (self.string(name), self.string(file));
+        if let Some(id) = self.function_ids.get(&key) {
+            return *id;
+        }
+        self.functions.push((key.0, key.1, start_line));
+        let id = self.functions.len() as u64;
+        self.function_ids.insert(key, id);
+        id
+    }
+
+    fn location(&mut self, function_id: u64, line: i64) -> u64 {
+        *self
+            .location_ids
+            .entry((function_id, line))
+            .or_insert_with(|| {
+                self.locations.push((function_id, line));
+                self.locations.len() as u64
+            })
+    }
+
+    fn push_samples(&mut self, profile: &Profile, id: NodeId, stack: &mut Vec<u64>) {
+        let node = &profile.tree.node(id).frame;
+        let file = file_name(profile, node.source.and_then(|source| source.index));
+        let line = node
+            .source
+            .and_then(|source| source.line)
+            .unwrap_or_default() as i64;
+        // the frame starts on the line of the definition of its function
+        let function_id = self.function(&node.name, &file, line);
+        // calls are made from the line of the frame, the exact call site is not known
+        let location = self.location(function_id, line);
+        stack.push(location);
+
+        // self gas by line, gas not mapped to a line stays on the line of the frame
+        let mut unmapped = profile.tree.self_gas(id);
+        if self.weight == Weight::Gas {
+            for cost in &node.costs {
+                let cost_file = file_name(profile, cost.source.index);
+                let cost_line = cost.source.line.unwrap_or_default() as i64;
+                let function_id = if cost_file == file {
+                    function_id
+                } else {
+                    self.function(&node.name, &cost_file, 0)
+                };
+                let cost_location = self.location(function_id, cost_line);
+                let mut values = vec![cost.gas as i64];
+                if self.with_instructions {
+                    values.push(0);
+                }
+                self.push_sample(stack, cost_location, values);
+                unmapped -= cost.gas as i64;
+            }
+        }
+
+        let mut values = vec![unmapped.max(0)];
+        if self.with_instructions {
+            values.push(node.instructions.unwrap_or_default() as i64);
+        }
+        self.push_sample(stack, location, values);
+
+        for child in &profile.tree.node(id).children {
+            self.push_samples(profile, *child, stack);
+        }
+
+        stack.pop();
+    }
+
+    /// Adds a sample of the frame on top of `stack`, spent at `location` of the frame.
+    fn push_sample(&mut self, stack: &[u64], location: u64, values: Vec<i64>) {
+        if values.iter().all(|value| *value == 0) {
+            return;
+        }
+        // pprof expects the leaf first
+        let locations = std::iter::once(location)
+            .chain(stack.iter().rev().skip(1).copied())
+            .collect();
+        self.samples.push((locations, values));
+    }
+
+    fn encode(mut self) -> Vec<u8> {
+        let mut sample_types = vec![match self.weight {
+            Weight::Gas => (self.string("gas"), self.string("gas")),
+            Weight::Instructions => (self.string("instructions"), self.string("count")),
+            Weight::Microseconds => (self.string("time"), self.string("microseconds")),
+        }];
+        if self.with_instructions {
+            sample_types.push((self.string("instructions"), self.string("count")));
+        }
+
+        let mut profile = Encoder::default();
+        for (kind, unit) in &sample_types {
+            profile.message(1, |value_type| {
+                value_type.int64(1, *kind);
+                value_type.int64(2, *unit);
+            });
+        }
+        for (locations, values) in &self.samples {
+            profile.message(2, |sample| {
+                sample.packed_uint64(1, locations);
+                sample.packed_int64(2, values);
+            });
+        }
+        for (i, (function_id, line)) in self.locations.iter().enumerate() {
+            profile.message(4, |location| {
+                location.uint64(1, i as u64 + 1);
+                location.message(4, |l| {
+                    l.uint64(1, *function_id);
+                    l.int64(2, *line);
+                });
+            });
+        }
+        for (i, (name, filename, start_line)) in self.functions.iter().enumerate() {
+            profile.message(5, |function| {
+                function.uint64(1, i as u64 + 1);
+                function.int64(2, *name);
+                function.int64(3, *name);
+                function.int64(4, *filename);
+                function.int64(5, *start_line);
+            });
+        }
+        for string in &self.strings {
+            profile.bytes(6, string.as_bytes());
+        }
+        let (kind, unit) = sample_types[0];
+        profile.message(11, |period_type| {
+            period_type.int64(1, kind);
+            period_type.int64(2, unit);
+        });
+        profile.int64(12, 1);
+
+        profile.buf
+    }
+}
+
+fn file_name(profile: &Profile, index: Option<u32>) -> String {
+    index
+        .and_then(|index| profile.sources.get(&index))
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+/// Minimal protobuf encoder. Fields with default values are skipped, as in proto3.
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.key(field, 0);
+            self.varint(value);
+        }
+    }
+
+    fn int64(&mut self, field: u32, value: i64) {
+        self.uint64(field, value as u64);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn message(&mut self, field: u32, f: impl FnOnce(&mut Encoder)) {
+        let mut message = Encoder::default();
+        f(&mut message);
+        self.bytes(field, &message.buf);
+    }
+
+    fn packed_uint64(&mut self, field: u32, values: &[u64]) {
+        let mut packed = Encoder::default();
+        for value in values {
+            packed.varint(*value);
+        }
+        self.bytes(field, &packed.buf);
+    }
+
+    fn packed_int64(&mut self, field: u32, values: &[i64]) {
+        let mut packed = Encoder::default();
+        for value in values {
+            packed.varint(*value as u64);
+        }
+        self.bytes(field, &packed.buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::profile::fixture;
+
+    #[test]
+    fn test_samples_by_line() {
+        let profile = fixture::profile();
+        let mut builder = Builder {
+            with_instructions: true,
+            ..Default::default()
+        };
+        builder.push_samples(&profile, profile.tree.root(), &mut vec![]);
+
+        let samples = builder
+            .samples
+            .iter()
+            .map(|(locations, values)| {
+                let lines = locations
+                    .iter()
+                    .map(|id| builder.locations[*id as usize - 1].1)
+                    .collect::<Vec<_>>();
+                (lines, values.clone())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            vec![
+                (vec![4], vec![50, 0]),
+                (vec![5], vec![15, 0]),
+                // the 5 gas left is not mapped to a line
+                (vec![3], vec![5, 10]),
+                (vec![9, 3], vec![30, 3]),
+            ]
+        );
+        let start_lines = builder
+            .functions
+            .iter()
+            .map(|(_, _, start_line)| *start_line)
+            .collect::<Vec<_>>();
+        assert_eq!(start_lines, vec![3, 9]);
+    }
+
+    #[test]
+    fn test_encode_varint() {
+        let mut encoder = Encoder::default();
+        encoder.uint64(1, 150);
+        assert_eq!(encoder.buf, vec![0x08, 0x96, 0x01]);
+
+        let mut encoder = Encoder::default();
+        encoder.int64(2, -1);
+        assert_eq!(encoder.buf.len(), 11);
+        assert_eq!(encoder.buf[0], 0x10);
+    }
+
+    #[test]
+    fn test_encode_message() {
+        let mut encoder = Encoder::default();
+        encoder.message(3, |message| message.packed_uint64(1, &[1, 2]));
+        assert_eq!(encoder.buf, vec![0x1a, 0x04, 0x0a, 0x02, 0x01, 0x02]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Writer for the [speedscope](https://www.speedscope.app) file format.
+//!
+//! See <https://github.com/jlfwong/speedscope/wiki/Importing-from-custom-sources>.
+
+use crate::{profile::Profile, tree::NodeId};
+use serde::Serialize;
+use std::{collections::HashMap, io::Write};
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    shared: Shared,
+    profiles: Vec<SpeedscopeProfile>,
+    name: String,
+    active_profile_index: usize,
+    exporter: String,
+}
+
+#[derive(Default, Serialize)]
+struct Shared {
+    frames: Vec<Frame>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize)]
+struct Frame {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum SpeedscopeProfile {
+    #[serde(rename_all = "camelCase")]
+    Evented {
+        name: String,
+        unit: &'static str,
+        start_value: u64,
+        end_value: u64,
+        events: Vec<Event>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Sampled {
+        name: String,
+        unit: &'static str,
+        start_value: u64,
+        end_value: u64,
+        samples: Vec<Vec<usize>>,
+        weights: Vec<u64>,
+    },
+}
+
+#[derive(Serialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    frame: usize,
+    at: u64,
+}
+
+/// Deduplicates the frames shared by the profiles, by label so that frames that reverted keep
+/// their reason.
+#[derive(Default)]
+struct Frames {
+    shared: Shared,
+    indices: HashMap<Frame, usize>,
+}
+
+impl Frames {
+    fn index(&mut self, profile: &Profile, id: NodeId) -> usize {
+        let node = &profile.tree.node(id).frame;
+        let frame = Frame {
+            name: node.label(),
+            file: node
+                .source
+                .and_then(|source| profile.sources.get(&source.index?))
+                .map(|path| path.display().to_string()),
+        };
+        *self.indices.entry(frame.clone()).or_insert_with(|| {
+            self.shared.frames.push(frame);
+            self.shared.frames.len() - 1
+        })
+    }
+}
+
+/// Writes the profile as a speedscope file.
+///
+/// Ordered profiles know where each frame starts and ends, so they are written as evented profiles
+/// which keep the execution order. Other profiles, e.g. from call traces, are written as sampled
+/// profiles of their folded stacks, weighted by gas.
+pub fn write<W: Write>(profile: &Profile, writer: W) -> eyre::Result<()> {
+    let mut frames = Frames::default();
+    let name = format!("{}::{}", profile.meta.contract, profile.meta.test);
+
+    let root = profile.tree.root();
+    let speedscope_profile = if profile.meta.ordered {
+        let mut events = vec![];
+        let start_value = profile.tree.node(root).frame.gas_start;
+        let mut at = start_value;
+        push_events(profile, root, &mut frames, &mut events, &mut at);
+        SpeedscopeProfile::Evented {
+            name: name.clone(),
+            unit: "none",
+            start_value,
+            end_value: at,
+            events,
+        }
+    } else {
+        let mut samples = vec![];
+        let mut weights = vec![];
+        push_samples(
+            profile,
+            root,
+            &mut frames,
+            &mut vec![],
+            &mut samples,
+            &mut weights,
+        );
+        SpeedscopeProfile::Sampled {
+            name: name.clone(),
+            unit: "none",
+            start_value: 0,
+            end_value: weights.iter().sum(),
+            samples,
+            weights,
+        }
+    };
+
+    let file = File {
+        schema: SCHEMA,
+        shared: frames.shared,
+        profiles: vec![speedscope_profile],
+        name,
+        active_profile_index: 0,
+        exporter: format!("forge-flamegraph@{}", env!("CARGO_PKG_VERSION")),
+    };
+    serde_json::to_writer(writer, &file)?;
+    Ok(())
+}
+
+/// Opens and closes the frame of the node `id` around its children.
+///
+/// Speedscope requires events to be ordered and properly nested, which gas ranges of frames that
+/// did not return do not guarantee, so `at` never goes backwards.
+fn push_events(
+    profile: &Profile,
+    id: NodeId,
+    frames: &mut Frames,
+    events: &mut Vec<Event>,
+    at: &mut u64,
+) {
+    let node = profile.tree.node(id);
+    let frame = frames.index(profile, id);
+
+    *at = (*at).max(node.frame.gas_start);
+    events.push(Event {
+        kind: "O",
+        frame,
+        at: *at,
+    });
+
+    for child in &node.children {
+        push_events(profile, *child, frames, events, at);
+    }
+
+    *at = (*at).max(node.frame.gas_end);
+    events.push(Event {
+        kind: "C",
+        frame,
+        at: *at,
+    });
+}
+
+fn push_samples(
+    profile: &Profile,
+    id: NodeId,
+    frames: &mut Frames,
+    stack: &mut Vec<usize>,
+    samples: &mut Vec<Vec<usize>>,
+    weights: &mut Vec<u64>,
+) {
+    stack.push(frames.index(profile, id));
+
+    let gas = profile.tree.self_gas(id) as u64;
+    if gas > 0 {
+        samples.push(stack.clone());
+        weights.push(gas);
+    }
+    for child in &profile.tree.node(id).children {
+        push_samples(profile, *child, frames, stack, samples, weights);
+    }
+
+    stack.pop();
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        profile::{fixture, FrameKind, Profile},
+        tree::Frame,
+    };
+
+    /// Returns the fixture profile with a second call to `_mint`.
+    fn profile(ordered: bool) -> Profile {
+        let mut profile = fixture::profile();
+        profile.meta.ordered = ordered;
+        let tree = &mut profile.tree;
+        tree.push(
+            tree.root(),
+            Frame {
+                gas_end: 90,
+                source: Some(fixture::location(9)),
+                ..Frame::new("_mint".to_string(), FrameKind::Internal, 80)
+            },
+        );
+        profile
+    }
+
+    #[test]
+    fn test_evented() {
+        let mut buf = vec![];
+        super::write(&profile(true), &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        assert_eq!(json["shared"]["frames"].as_array().unwrap().len(), 2);
+        let profile = &json["profiles"][0];
+        assert_eq!(profile["type"], "evented");
+        assert_eq!(profile["endValue"], 100);
+        let ats = profile["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["at"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ats, vec![0, 40, 70, 80, 90, 100]);
+    }
+
+    #[test]
+    fn test_sampled() {
+        let mut buf = vec![];
+        super::write(&profile(false), &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        let profile = &json["profiles"][0];
+        assert_eq!(profile["type"], "sampled");
+        assert_eq!(profile["endValue"], 100);
+        assert_eq!(profile["samples"], serde_json::json!([[0], [0, 1], [0, 1]]));
+        assert_eq!(profile["weights"], serde_json::json!([60, 30, 10]));
+    }
+
+    #[test]
+    fn test_reverted_frames() {
+        let mut profile = profile(false);
+        let mint = profile.tree.nodes()[2].id;
+        profile.tree.frame_mut(mint).revert = Some("cap exceeded".to_string());
+
+        let mut buf = vec![];
+        super::write(&profile, &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        // the call that reverted is not merged with the one that returned
+        let names = json["shared"]["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| frame["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Vault.deposit", "_mint", "_mint [reverted: cap exceeded]"]
+        );
+        let profile = &json["profiles"][0];
+        assert_eq!(profile["samples"], serde_json::json!([[0], [0, 1], [0, 2]]));
+    }
+}
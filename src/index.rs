@@ -0,0 +1,106 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+/// A row of the batch index page.
+#[derive(Clone, Debug)]
+pub struct IndexEntry {
+    pub contract: String,
+    pub test: String,
+    /// Total gas reported by the test result.
+    pub gas: u64,
+    /// Path of the generated flamegraph, if profiling succeeded.
+    pub file: Option<String>,
+    /// Reason the flamegraph could not be generated.
+    pub error: Option<String>,
+}
+
+/// Writes an html page linking every generated flamegraph, sorted by gas (highest first).
+pub fn write_index(path: impl AsRef<Path>, entries: &[IndexEntry]) -> eyre::Result<()> {
+    fs::write(path, render_index(entries))?;
+    Ok(())
+}
+
+pub fn render_index(entries: &[IndexEntry]) -> String {
+    let mut entries = entries.to_vec();
+    entries.sort_by(|a, b| b.gas.cmp(&a.gas).then_with(|| a.test.cmp(&b.test)));
+
+    let mut rows = String::new();
+    for entry in &entries {
+        let link = match (&entry.file, &entry.error) {
+            (Some(file), _) => format!("<a href=\"{0}\">{0}</a>", escape(file)),
+            (None, Some(error)) => format!("<span class=\"error\">{}</span>", escape(error)),
+            (None, None) => String::new(),
+        };
+        writeln!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td class=\"gas\">{}</td><td>{}</td></tr>",
+            escape(&entry.contract),
+            escape(&entry.test),
+            entry.gas,
+            link
+        )
+        .unwrap();
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>forge-flamegraph</title>
+<style>
+body {{ font-family: monospace; }}
+table {{ border-collapse: collapse; }}
+td, th {{ padding: 2px 12px; text-align: left; }}
+tr:nth-child(even) {{ background: #f4f4f4; }}
+.gas {{ text-align: right; }}
+.error {{ color: #c00; }}
+</style>
+</head>
+<body>
+<h1>forge-flamegraph</h1>
+<table>
+<tr><th>Contract</th><th>Test</th><th class="gas">Gas</th><th>Flamegraph</th></tr>
+{rows}</table>
+</body>
+</html>
+"#
+    )
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_index_sorted_by_gas() {
+        let entries = vec![
+            IndexEntry {
+                contract: "A".to_string(),
+                test: "testCheap".to_string(),
+                gas: 10,
+                file: Some("cheap.svg".to_string()),
+                error: None,
+            },
+            IndexEntry {
+                contract: "B".to_string(),
+                test: "testExpensive".to_string(),
+                gas: 1000,
+                file: None,
+                error: Some("debug arena is empty".to_string()),
+            },
+        ];
+        let html = render_index(&entries);
+        let expensive = html.find("testExpensive").unwrap();
+        let cheap = html.find("testCheap").unwrap();
+        assert!(expensive < cheap);
+        assert!(html.contains("<a href=\"cheap.svg\">"));
+        assert!(html.contains("debug arena is empty"));
+    }
+}
@@ -0,0 +1,156 @@
+//! Invariant tests, profiled as sequences of handler calls.
+//!
+//! Every handler call of a sequence is profiled on its own, and becomes a frame under a root
+//! named after the test, e.g. `Handler.deposit(uint256)` and `Handler.withdraw(uint256)`.
+
+use crate::{
+    profile::FrameKind,
+    tree::{CallTree, Frame},
+};
+use forge::{result::TestStatus, traces::TraceKind};
+use foundry_evm_traces::CallTraceArena;
+
+/// Returns the calls of the sequence replayed by forge, along with the invariant checks made
+/// after them. The deployment and `setUp` of the test contract are left out.
+pub fn sequence_calls(traces: &[(TraceKind, CallTraceArena)]) -> Vec<&CallTraceArena> {
+    traces
+        .iter()
+        .filter(|(kind, _)| *kind == TraceKind::Execution)
+        .map(|(_, arena)| arena)
+        .collect()
+}
+
+/// Describes the sequence replayed by forge, shown as the subtitle.
+pub fn sequence_name(status: &TestStatus, calls: usize) -> String {
+    let sequence = if *status == TestStatus::Failure {
+        "failing sequence"
+    } else {
+        "last run"
+    };
+    format!("{sequence} of {calls} calls")
+}
+
+/// Builds the call tree of a sequence from the call trees of its calls, in order.
+///
+/// Each call keeps the name of its root, e.g. `Handler.deposit(uint256)`, and is laid out after
+/// the previous one under a root named `test_name`, which spans the whole sequence.
+pub fn sequence_tree(test_name: &str, calls: &[CallTree]) -> CallTree {
+    let mut tree = CallTree::new(Frame::new(test_name.to_string(), FrameKind::Call, 0));
+    for call in calls {
+        tree.append(tree.root(), call);
+    }
+    tree
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(name: &str, gas_start: u64, gas_end: u64) -> CallTree {
+        CallTree::new(Frame {
+            gas_end,
+            ..Frame::new(name.to_string(), FrameKind::Call, gas_start)
+        })
+    }
+
+    fn frames(tree: &CallTree) -> Vec<(&str, u64, u64)> {
+        tree.nodes()
+            .iter()
+            .map(|node| {
+                let frame = &node.frame;
+                (frame.name.as_str(), frame.gas_start, frame.gas_end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sequence_calls() {
+        let traces = vec![
+            (TraceKind::Deployment, CallTraceArena::default()),
+            (TraceKind::Setup, CallTraceArena::default()),
+            (TraceKind::Execution, CallTraceArena::default()),
+            (TraceKind::Execution, CallTraceArena::default()),
+        ];
+        let calls = sequence_calls(&traces);
+        assert_eq!(calls.len(), 2);
+        assert!(std::ptr::eq(calls[0], &traces[2].1));
+        assert!(std::ptr::eq(calls[1], &traces[3].1));
+
+        assert_eq!(
+            sequence_name(&TestStatus::Failure, 2),
+            "failing sequence of 2 calls"
+        );
+        assert_eq!(
+            sequence_name(&TestStatus::Success, 3),
+            "last run of 3 calls"
+        );
+    }
+
+    #[test]
+    fn test_sequence_tree() {
+        let mut deposit = call("Handler.deposit(uint256)", 0, 30);
+        let mut transfer = Frame::new("Token.transferFrom".to_string(), FrameKind::Call, 5);
+        transfer.gas_end = 20;
+        deposit.push(deposit.root(), transfer);
+        let mut withdraw = call("Handler.withdraw(uint256)", 0, 50);
+        withdraw.frame_mut(withdraw.root()).revert = Some("insufficient balance".to_string());
+        // the gas of a call trace doesn't have to start at 0
+        let second_deposit = call("Handler.deposit(uint256)", 100, 130);
+
+        let tree = sequence_tree("invariant_solvency()", &[deposit, withdraw, second_deposit]);
+        assert_eq!(
+            frames(&tree),
+            vec![
+                ("invariant_solvency()", 0, 110),
+                ("Handler.deposit(uint256)", 0, 30),
+                ("Token.transferFrom", 5, 20),
+                ("Handler.withdraw(uint256)", 30, 80),
+                ("Handler.deposit(uint256)", 80, 110),
+            ]
+        );
+        // every call is a frame under the test, the calls they make stay under them
+        let calls = tree
+            .children(tree.root())
+            .map(|node| node.id)
+            .collect::<Vec<_>>();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(tree.nodes()[2].parent, Some(calls[0]));
+        assert_eq!(
+            tree.node(calls[1]).frame.revert.as_deref(),
+            Some("insufficient balance")
+        );
+
+        assert_eq!(frames(&sequence_tree("invariant_solvency()", &[])).len(), 1);
+    }
+
+    #[test]
+    fn test_average_sequences() {
+        let runs = [
+            sequence_tree(
+                "invariant_solvency()",
+                &[
+                    call("Handler.deposit(uint256)", 0, 30),
+                    call("Handler.withdraw(uint256)", 0, 50),
+                ],
+            ),
+            sequence_tree(
+                "invariant_solvency()",
+                &[
+                    call("Handler.deposit(uint256)", 0, 30),
+                    call("Handler.deposit(uint256)", 0, 30),
+                ],
+            ),
+        ];
+
+        // the calls to a handler are merged, within and across runs
+        let average = CallTree::average(&runs).unwrap();
+        assert_eq!(
+            frames(&average),
+            vec![
+                ("invariant_solvency()", 0, 70),
+                ("Handler.deposit(uint256)", 0, 45),
+                ("Handler.withdraw(uint256)", 45, 70),
+            ]
+        );
+    }
+}
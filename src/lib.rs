@@ -1,4 +1,14 @@
 pub mod backends;
 pub mod cli;
+pub mod config;
+pub mod diff;
 pub mod flamegraph;
 pub mod forge;
+pub mod format;
+pub mod index;
+pub mod invariant;
+pub mod output;
+pub mod profile;
+pub mod replay;
+pub mod selector;
+pub mod tree;
@@ -3,7 +3,7 @@ use eyre::Result;
 use foundry_cli::{self, handler};
 use foundry_evm::inspectors::cheatcodes::{set_execution_context, ForgeContext};
 
-use forge_flamegraph::cli::FlamegraphArgs;
+use forge_flamegraph::cli::{FlamegraphArgs, FlamegraphSubcommand};
 
 fn main() -> Result<()> {
     handler::install();
@@ -11,7 +11,15 @@ fn main() -> Result<()> {
     foundry_cli::utils::subscriber();
     foundry_cli::utils::enable_paint();
 
-    let flamegraph = FlamegraphArgs::parse();
+    let mut flamegraph = FlamegraphArgs::parse();
+    match flamegraph.command.take() {
+        Some(FlamegraphSubcommand::Report(report)) => return report.run(),
+        Some(FlamegraphSubcommand::Backends) => {
+            flamegraph.list_backends();
+            return Ok(());
+        }
+        None => {}
+    }
     set_execution_context(ForgeContext::Test);
 
     let outcome = foundry_cli::utils::block_on(flamegraph.run())?;
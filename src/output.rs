@@ -0,0 +1,246 @@
+//! Paths of the files written for the profiled tests.
+
+use crate::format::OutputFormat;
+use clap::ValueEnum;
+use eyre::Result;
+use foundry_cli::utils::{CommandUtils, Git};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Default name of the outputs of a test, without extension.
+pub const DEFAULT_TEMPLATE: &str = "flamegraph_{contract}_{test}_{backend}";
+
+const PLACEHOLDERS: [&str; 5] = ["contract", "test", "backend", "timestamp", "sha"];
+
+/// Name of the outputs of a test, e.g. `{contract}/{test}_{sha}`.
+///
+/// The placeholders are `{contract}`, `{test}`, `{backend}`, `{timestamp}` (UTC, as
+/// `20240101-120000`) and `{sha}` (short git commit hash of the project).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self(DEFAULT_TEMPLATE.to_string())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                eyre::bail!("unclosed `{{` in name template `{s}`");
+            };
+            let placeholder = &rest[start + 1..start + end];
+            eyre::ensure!(
+                PLACEHOLDERS.contains(&placeholder),
+                "unknown placeholder `{{{placeholder}}}` in `{s}`, expected one of {}",
+                PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+            );
+            rest = &rest[start + end + 1..];
+        }
+        eyre::ensure!(!s.trim().is_empty(), "empty name template");
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl NameTemplate {
+    fn uses(&self, placeholder: &str) -> bool {
+        self.0.contains(&format!("{{{placeholder}}}"))
+    }
+
+    /// Replaces the placeholders with their `values`.
+    fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut name = self.0.clone();
+        for (placeholder, value) in values {
+            name = name.replace(&format!("{{{placeholder}}}"), value);
+        }
+        name
+    }
+}
+
+/// Where the outputs of the tests are written, and whether existing files may be overwritten.
+#[derive(Clone, Debug, Default)]
+pub struct OutputPaths {
+    /// Directory of the outputs, the current directory if empty.
+    pub dir: PathBuf,
+    /// Path of the outputs without extension, when `--output` names a file. Only one test can be
+    /// written there.
+    pub file: Option<PathBuf>,
+    template: NameTemplate,
+    force: bool,
+    timestamp: String,
+    sha: String,
+}
+
+impl OutputPaths {
+    /// Resolves `--output`, which is a directory if it exists as one, ends with a separator or has
+    /// no extension, and a file otherwise. `dir` is used when `--output` is not given.
+    ///
+    /// The git commit hash is only looked up in `root` if the template uses it.
+    pub fn new(
+        out: Option<&Path>,
+        dir: Option<&Path>,
+        template: NameTemplate,
+        force: bool,
+        root: &Path,
+    ) -> Result<Self> {
+        let (dir, file) = match out {
+            Some(out) if !is_dir(out) => (
+                out.parent().unwrap_or(Path::new("")).to_path_buf(),
+                Some(strip_extension(out)),
+            ),
+            Some(out) => (out.to_path_buf(), None),
+            None => (dir.map(Path::to_path_buf).unwrap_or_default(), None),
+        };
+
+        let sha = if template.uses("sha") {
+            let output = Git::new(root)
+                .cmd()
+                .args(["rev-parse", "--short", "HEAD"])
+                .exec()?;
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        } else {
+            String::new()
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        Ok(Self {
+            dir,
+            file,
+            template,
+            force,
+            timestamp: timestamp(now),
+            sha,
+        })
+    }
+
+    /// Returns the path, without extension, of the outputs of a test.
+    pub fn stem(&self, contract: &str, test: &str, backend: &str) -> PathBuf {
+        if let Some(file) = &self.file {
+            return file.clone();
+        }
+
+        // `test/Vault.t.sol:VaultTest` is named `VaultTest`
+        let contract = contract.rsplit(':').next().unwrap_or(contract);
+        let test = test.strip_suffix("()").unwrap_or(test);
+        self.dir.join(self.template.render(&[
+            ("contract", contract),
+            ("test", test),
+            ("backend", backend),
+            ("timestamp", &self.timestamp),
+            ("sha", &self.sha),
+        ]))
+    }
+
+    /// Fails if `path` exists and overwriting was not allowed with `--force`.
+    pub fn check_overwrite(&self, path: &Path) -> Result<()> {
+        eyre::ensure!(
+            self.force || !path.exists(),
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+fn is_dir(path: &Path) -> bool {
+    path.is_dir()
+        || path
+            .as_os_str()
+            .to_string_lossy()
+            .ends_with(std::path::is_separator)
+        || path.extension().is_none()
+}
+
+/// Strips the extension of the output formats, e.g. both `gas.svg` and `gas.trace.json` give
+/// `gas`.
+fn strip_extension(path: &Path) -> PathBuf {
+    let Some(file_name) = path.file_name() else {
+        return path.to_path_buf();
+    };
+    let file_name = file_name.to_string_lossy();
+    let stem = OutputFormat::value_variants()
+        .iter()
+        .map(|format| format.extension())
+        .chain(["profile.json"])
+        .find_map(|extension| file_name.strip_suffix(&format!(".{extension}")))
+        .or_else(|| file_name.rsplit_once('.').map(|(stem, _)| stem))
+        .unwrap_or(&file_name);
+    path.with_file_name(stem)
+}
+
+/// Formats seconds since the unix epoch as `YYYYMMDD-HHMMSS` in UTC.
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let secs = secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let template = "{contract}/{test}_{sha}".parse::<NameTemplate>().unwrap();
+        let paths = OutputPaths {
+            dir: PathBuf::from("out"),
+            template,
+            sha: "abc1234".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths.stem("test/Vault.t.sol:VaultTest", "testDeposit()", "debug"),
+            Path::new("out/VaultTest/testDeposit_abc1234")
+        );
+
+        assert!("flamegraph_{name}".parse::<NameTemplate>().is_err());
+        assert!("flamegraph_{test".parse::<NameTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_out() {
+        assert!(is_dir(Path::new("flamegraphs")));
+        assert!(is_dir(Path::new("out/v1.2/")));
+        assert!(!is_dir(Path::new("out/gas.svg")));
+        assert_eq!(
+            strip_extension(Path::new("out/gas.svg")),
+            Path::new("out/gas")
+        );
+        assert_eq!(
+            strip_extension(Path::new("gas.trace.json")),
+            Path::new("gas")
+        );
+        assert_eq!(strip_extension(Path::new("gas.v2")), Path::new("gas"));
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "19700101-000000");
+        assert_eq!(timestamp(1_709_210_096), "20240229-123456");
+    }
+}
@@ -0,0 +1,317 @@
+use crate::tree::CallTree;
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Version of the saved profile format, bumped on incompatible changes.
+pub const PROFILE_VERSION: u32 = 2;
+
+/// The raw profile of a test.
+///
+/// Profiles can be saved and reloaded, so that outputs can be regenerated without compiling and
+/// running the test again.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub version: u32,
+    pub meta: ProfileMeta,
+    /// Paths of the source files, relative to the project root, by source id.
+    pub sources: BTreeMap<u32, PathBuf>,
+    pub tree: CallTree,
+}
+
+/// Describes the test a profile was taken from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfileMeta {
+    /// Identifier of the test contract, e.g. `test/Vault.t.sol:VaultTest`.
+    pub contract: String,
+    pub test: String,
+    /// Name of the backend that produced the call tree, `debug` or `calltrace`.
+    pub backend: String,
+    pub status: String,
+    /// Total gas reported by the test result.
+    pub gas: u64,
+    /// Whether stacks were merged. Unmerged debug profiles contain a frame per opcode and are
+    /// rendered as flame charts.
+    pub merge_stacks: bool,
+    /// Root of the project the source paths are relative to.
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    /// Shown under the title of the svg, e.g. the inputs of the profiled fuzz run.
+    #[serde(default)]
+    pub subtitle: Option<String>,
+    /// What the gas ranges of the frames measure.
+    #[serde(default)]
+    pub weight: Weight,
+    /// Whether the frames start where they ran within their parent, as when stepping through the
+    /// code. Otherwise children are laid out one after the other, e.g. from a call trace.
+    #[serde(default)]
+    pub ordered: bool,
+}
+
+/// What the frames of a profile are measured in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Weight {
+    /// Gas used.
+    #[default]
+    Gas,
+    /// Instructions executed, as counted by backends that step through the code.
+    Instructions,
+    /// Wall-clock time, as recorded by the timing backend, which picks it itself.
+    #[value(skip)]
+    Microseconds,
+}
+
+impl Weight {
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Weight::Gas => "gas",
+            Weight::Instructions => "instructions",
+            Weight::Microseconds => "µs",
+        }
+    }
+}
+
+/// What a frame of the call tree stands for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameKind {
+    #[default]
+    Call,
+    StaticCall,
+    CallCode,
+    DelegateCall,
+    Create,
+    Create2,
+    /// A jump into an internal solidity function.
+    Internal,
+    /// A single opcode, only present in unmerged debug profiles, or a class of opcodes in timing
+    /// profiles.
+    Opcode,
+    /// Gas reported by the test but missing from the call tree, see [`CallTree::reconcile_gas`].
+    Unattributed,
+}
+
+impl FrameKind {
+    /// Returns whether the frame is a new call context, i.e. an external call or a deployment.
+    pub fn is_external(&self) -> bool {
+        !matches!(
+            self,
+            FrameKind::Internal | FrameKind::Opcode | FrameKind::Unattributed
+        )
+    }
+}
+
+/// Name of the frame holding the gas missing from the call tree.
+pub const UNATTRIBUTED: &str = "<unattributed>";
+
+/// Comparison of the gas of a call tree with the gas reported by the test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasCheck {
+    /// Gas reported by the test result.
+    pub reported: u64,
+    /// Gas of the call tree, before the missing gas was attributed.
+    pub profiled: i64,
+    /// Gas of children exceeding the gas of their parent, which is counted twice and dropped
+    /// from the self gas of the parent.
+    pub overlapping: u64,
+}
+
+impl GasCheck {
+    /// Returns the gas reported but missing from the call tree, negative if the call tree has
+    /// more gas than reported.
+    pub fn difference(&self) -> i64 {
+        self.reported as i64 - self.profiled
+    }
+
+    /// Returns whether the difference is more than `percent` of the reported gas.
+    pub fn exceeds(&self, percent: f64) -> bool {
+        self.difference().unsigned_abs() as f64 > self.reported as f64 * percent / 100.0
+    }
+}
+
+/// Location of a frame in the solidity sources, as given by the compiler's source map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    /// Source id, see [`Profile::sources`].
+    pub index: Option<u32>,
+    pub offset: usize,
+    pub length: usize,
+    /// 1-based line of `offset`.
+    #[serde(default)]
+    pub line: Option<usize>,
+}
+
+/// Gas spent on the instructions mapped to a source range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceCost {
+    pub source: SourceLocation,
+    pub gas: u64,
+}
+
+impl SourceLocation {
+    /// Returns whether both locations point to the same source range.
+    pub fn same_range(&self, other: &SourceLocation) -> bool {
+        self.index == other.index && self.offset == other.offset && self.length == other.length
+    }
+}
+
+/// Resolves byte offsets in source files to line numbers.
+#[derive(Clone, Debug, Default)]
+pub struct LineIndex {
+    /// Offsets at which lines start, by source id.
+    line_starts: HashMap<u32, Vec<usize>>,
+}
+
+impl LineIndex {
+    pub fn new<'a>(sources: impl IntoIterator<Item = (u32, &'a str)>) -> Self {
+        let line_starts = sources
+            .into_iter()
+            .map(|(id, source)| {
+                let starts = std::iter::once(0)
+                    .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+                    .collect();
+                (id, starts)
+            })
+            .collect();
+        Self { line_starts }
+    }
+
+    /// Returns the 1-based line of `offset` in the source `index`.
+    pub fn line(&self, index: u32, offset: usize) -> Option<usize> {
+        let starts = self.line_starts.get(&index)?;
+        Some(starts.partition_point(|&start| start <= offset))
+    }
+}
+
+impl Profile {
+    pub fn new(meta: ProfileMeta, sources: BTreeMap<u32, PathBuf>, tree: CallTree) -> Self {
+        Self {
+            version: PROFILE_VERSION,
+            meta,
+            sources,
+            tree,
+        }
+    }
+
+    /// Writes the profile as json.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = fs::File::create(path)
+            .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Reads a profile written by [`Profile::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            fs::File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+        let profile: Self = serde_json::from_reader(std::io::BufReader::new(file))
+            .wrap_err_with(|| format!("failed to parse profile {}", path.display()))?;
+        eyre::ensure!(
+            profile.version == PROFILE_VERSION,
+            "unsupported profile version {}, expected {PROFILE_VERSION}",
+            profile.version
+        );
+        Ok(profile)
+    }
+}
+
+/// Profile of `VaultTest::testDeposit`, shared by the tests of the output formats.
+#[cfg(test)]
+pub(crate) mod fixture {
+    use super::*;
+    use crate::tree::Frame;
+
+    /// Returns a location on `line` of `src/Vault.sol`.
+    pub fn location(line: usize) -> SourceLocation {
+        SourceLocation {
+            index: Some(0),
+            offset: 0,
+            length: 0,
+            line: Some(line),
+        }
+    }
+
+    pub fn meta(backend: &str, ordered: bool) -> ProfileMeta {
+        ProfileMeta {
+            contract: "test/Vault.t.sol:VaultTest".to_string(),
+            test: "testDeposit()".to_string(),
+            backend: backend.to_string(),
+            status: "Success".to_string(),
+            gas: 100,
+            merge_stacks: true,
+            root: None,
+            subtitle: None,
+            weight: Weight::Gas,
+            ordered,
+        }
+    }
+
+    /// Returns a debug profile of `Vault.deposit` on line 3, which spends 100 gas: 50 on line 4,
+    /// 15 on line 5, 5 not mapped to a line and 30 in `_mint` on line 9.
+    pub fn profile() -> Profile {
+        let mut tree = CallTree::new(Frame {
+            gas_end: 100,
+            source: Some(location(3)),
+            instructions: Some(10),
+            costs: vec![
+                SourceCost {
+                    source: location(4),
+                    gas: 50,
+                },
+                SourceCost {
+                    source: location(5),
+                    gas: 15,
+                },
+            ],
+            ..Frame::new("Vault.deposit".to_string(), FrameKind::Call, 0)
+        });
+        tree.push(
+            tree.root(),
+            Frame {
+                gas_end: 70,
+                source: Some(location(9)),
+                instructions: Some(3),
+                ..Frame::new("_mint".to_string(), FrameKind::Internal, 40)
+            },
+        );
+        Profile::new(
+            meta("debug", true),
+            BTreeMap::from([(0, PathBuf::from("src/Vault.sol"))]),
+            tree,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_profile_round_trip() {
+        let profile = fixture::profile();
+        let json = serde_json::to_string(&profile).unwrap();
+        let loaded: Profile = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.meta.test, "testDeposit()");
+        assert_eq!(loaded.sources[&0], PathBuf::from("src/Vault.sol"));
+        assert_eq!(loaded.tree, profile.tree);
+    }
+
+    #[test]
+    fn test_line_index() {
+        let lines = LineIndex::new([(0, "contract A {\n    function f() {}\n}\n")]);
+        assert_eq!(lines.line(0, 0), Some(1));
+        assert_eq!(lines.line(0, 12), Some(1));
+        assert_eq!(lines.line(0, 13), Some(2));
+        assert_eq!(lines.line(0, 33), Some(3));
+        assert_eq!(lines.line(1, 0), None);
+    }
+}
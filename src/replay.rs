@@ -0,0 +1,112 @@
+//! Replays the calls of a test on an executor of our own, set up like the one of forge's test
+//! runner, for what the runner doesn't record, e.g. the debug steps of a sampled fuzz run.
+
+use alloy_primitives::{Address, U256};
+use eyre::Result;
+use forge::{inspectors::CheatsConfig, multi_runner::TestContract, MultiContractRunner};
+use foundry_evm::{
+    backend::Backend,
+    constants::{CALLER, LIBRARY_DEPLOYER},
+    decode::RevertDecoder,
+    executors::{Executor, ExecutorBuilder, RawCallResult},
+    fork::CreateFork,
+    opts::EvmOpts,
+};
+use foundry_evm_traces::CallTrace;
+use revm::primitives::{Env, SpecId};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Deploys test contracts and replays their calls, on a fresh executor every time.
+pub struct TestReplayer {
+    /// Test contracts by identifier, e.g. `test/Vault.t.sol:VaultTest`.
+    contracts: BTreeMap<String, TestContract>,
+    env: Env,
+    evm_opts: EvmOpts,
+    evm_spec: SpecId,
+    sender: Address,
+    fork: Option<CreateFork>,
+    cheats_config: Arc<CheatsConfig>,
+    revert_decoder: RevertDecoder,
+}
+
+impl TestReplayer {
+    /// Takes the settings of `runner`, before it runs the tests.
+    pub fn new(runner: &MultiContractRunner) -> Self {
+        Self {
+            contracts: runner
+                .contracts
+                .iter()
+                .map(|(id, contract)| (id.identifier(), contract.clone()))
+                .collect(),
+            env: runner.env.clone(),
+            evm_opts: runner.evm_opts.clone(),
+            evm_spec: runner.evm_spec,
+            sender: runner.sender.unwrap_or_default(),
+            fork: runner.fork.clone(),
+            cheats_config: runner.cheats_config.clone(),
+            revert_decoder: runner.revert_decoder.clone(),
+        }
+    }
+
+    /// Deploys the test contract `contract` and calls its `setUp`, as forge's `ContractRunner`
+    /// does, so that it is deployed at the same address. The executor records the debug steps of
+    /// its calls if `debug` is set.
+    pub fn setup(&self, contract: &str, debug: bool) -> Result<Executor> {
+        let test_contract = self
+            .contracts
+            .get(contract)
+            .ok_or_else(|| eyre::eyre!("{contract} is not a test contract"))?;
+        let mut executor = ExecutorBuilder::new()
+            .inspectors(|stack| {
+                stack
+                    .cheatcodes(self.cheats_config.clone())
+                    .trace(debug)
+                    .debug(debug)
+            })
+            .spec(self.evm_spec)
+            .gas_limit(self.evm_opts.gas_limit())
+            .build(self.env.clone(), Backend::spawn(self.fork.clone()));
+
+        let initial_balance = self.evm_opts.initial_balance;
+        executor.set_balance(self.sender, U256::MAX)?;
+        executor.set_balance(CALLER, U256::MAX)?;
+        // the nonce of forge, so that the test contract gets the same address
+        executor.set_nonce(self.sender, 1)?;
+        for code in &test_contract.libs_to_deploy {
+            executor
+                .deploy(
+                    LIBRARY_DEPLOYER,
+                    code.clone(),
+                    U256::ZERO,
+                    Some(&self.revert_decoder),
+                )
+                .map_err(|err| eyre::eyre!("failed to deploy a library of {contract}: {err}"))?;
+        }
+        let address = self.sender.create(executor.get_nonce(self.sender)?);
+        executor.set_balance(address, initial_balance)?;
+        executor
+            .deploy(
+                self.sender,
+                test_contract.bytecode.clone(),
+                U256::ZERO,
+                Some(&self.revert_decoder),
+            )
+            .map_err(|err| eyre::eyre!("failed to deploy {contract}: {err}"))?;
+        executor.set_balance(self.sender, initial_balance)?;
+        executor.set_balance(CALLER, initial_balance)?;
+        executor.deploy_create2_deployer()?;
+
+        if test_contract.abi.function("setUp").is_some() {
+            executor
+                .setup(None, address, Some(&self.revert_decoder))
+                .map_err(|err| eyre::eyre!("setUp of {contract} failed: {err}"))?;
+        }
+        Ok(executor)
+    }
+
+    /// Replays `call`, e.g. the root of the trace of a fuzz run, on the state after `setUp`.
+    pub fn replay(&self, contract: &str, call: &CallTrace, debug: bool) -> Result<RawCallResult> {
+        let executor = self.setup(contract, debug)?;
+        executor.call_raw(call.caller, call.address, call.data.clone(), call.value)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::forge::test::FilterArgs;
+use regex::Regex;
+use std::{fmt, str::FromStr};
+
+/// Selects a single test by `test`, `Contract::test` or `path:Contract::test`.
+///
+/// Unlike `--match-test`, the parts of a selector are matched exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestSelector {
+    pub path: Option<String>,
+    pub contract: Option<String>,
+    pub test: String,
+}
+
+impl FromStr for TestSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (contract_part, test) = match s.rsplit_once("::") {
+            Some((contract_part, test)) => (Some(contract_part), test),
+            None => (None, s),
+        };
+        if test.is_empty() {
+            return Err(format!("missing test name in selector `{s}`"));
+        }
+
+        let (path, contract) = match contract_part.map(|c| c.rsplit_once(':').unwrap_or(("", c))) {
+            Some((path, contract)) => {
+                if contract.is_empty() {
+                    return Err(format!("missing contract name in selector `{s}`"));
+                }
+                (
+                    (!path.is_empty()).then(|| path.to_string()),
+                    Some(contract.to_string()),
+                )
+            }
+            None => (None, None),
+        };
+
+        Ok(Self {
+            path,
+            contract,
+            test: test.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for TestSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{path}:")?;
+        }
+        if let Some(contract) = &self.contract {
+            write!(f, "{contract}::")?;
+        }
+        write!(f, "{}", self.test)
+    }
+}
+
+impl TestSelector {
+    /// Narrows down the filter to the selected test.
+    ///
+    /// Fails if a part of the selector is also set through the corresponding `--match-*` flag.
+    pub fn apply(&self, filter: &mut FilterArgs) -> eyre::Result<()> {
+        if filter.test_pattern.is_some() {
+            eyre::bail!("Cannot specify both a test selector and --match-test.");
+        }
+        // test functions are matched by their signature, e.g. `testDeposit(uint256)`
+        filter.test_pattern = Some(Regex::new(&format!("^{}\\(", regex::escape(&self.test)))?);
+
+        if let Some(contract) = &self.contract {
+            if filter.contract_pattern.is_some() {
+                eyre::bail!(
+                    "Cannot specify both a contract in the test selector and --match-contract."
+                );
+            }
+            filter.contract_pattern = Some(Regex::new(&format!("^{}$", regex::escape(contract)))?);
+        }
+
+        if let Some(path) = &self.path {
+            if filter.path_pattern.is_some() {
+                eyre::bail!("Cannot specify both a path in the test selector and --match-path.");
+            }
+            filter.path_pattern = Some(path.parse()?);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TestSelector;
+
+    #[test]
+    fn test_parse_test_only() {
+        let selector: TestSelector = "testDeposit".parse().unwrap();
+        assert_eq!(selector.path, None);
+        assert_eq!(selector.contract, None);
+        assert_eq!(selector.test, "testDeposit");
+    }
+
+    #[test]
+    fn test_parse_contract_and_test() {
+        let selector: TestSelector = "VaultTest::testDeposit".parse().unwrap();
+        assert_eq!(selector.path, None);
+        assert_eq!(selector.contract.as_deref(), Some("VaultTest"));
+        assert_eq!(selector.test, "testDeposit");
+    }
+
+    #[test]
+    fn test_parse_path_contract_and_test() {
+        let selector: TestSelector = "test/Vault.t.sol:VaultTest::testDeposit".parse().unwrap();
+        assert_eq!(selector.path.as_deref(), Some("test/Vault.t.sol"));
+        assert_eq!(selector.contract.as_deref(), Some("VaultTest"));
+        assert_eq!(selector.test, "testDeposit");
+        assert_eq!(
+            selector.to_string(),
+            "test/Vault.t.sol:VaultTest::testDeposit"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("VaultTest::".parse::<TestSelector>().is_err());
+        assert!("test/Vault.t.sol:::testDeposit"
+            .parse::<TestSelector>()
+            .is_err());
+    }
+}
@@ -0,0 +1,568 @@
+//! Call tree stored as an arena of nodes that refer to each other by id.
+//!
+//! A node can be looked up by id and walked up to the root. The tree is `Send + Sync` and
+//! serializable, so it can be post-processed in parallel and persisted in a
+//! [`Profile`](crate::profile::Profile).
+
+use crate::profile::{FrameKind, GasCheck, LineIndex, SourceCost, SourceLocation, UNATTRIBUTED};
+use alloy_primitives::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Id of a node, its index in the arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// What a node of the call tree ran and spent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub name: String,
+    pub gas_start: u64,
+    pub gas_end: u64,
+    pub kind: FrameKind,
+    /// Address of the contract the frame executes in.
+    pub address: Option<Address>,
+    pub source: Option<SourceLocation>,
+    /// Number of instructions executed by the frame itself, excluding its children, if the
+    /// backend counts them.
+    pub instructions: Option<u64>,
+    /// Gas used by the frame itself, by source range, if the backend steps through the code.
+    #[serde(default)]
+    pub costs: Vec<SourceCost>,
+    /// Decoded revert reason, if the frame reverted.
+    #[serde(default)]
+    pub revert: Option<String>,
+}
+
+impl Frame {
+    /// Returns a frame starting at `gas_start` that used no gas yet.
+    pub fn new(name: String, kind: FrameKind, gas_start: u64) -> Self {
+        Self {
+            name,
+            gas_start,
+            gas_end: gas_start,
+            kind,
+            address: None,
+            source: None,
+            instructions: None,
+            costs: vec![],
+            revert: None,
+        }
+    }
+
+    /// Accounts `gas` spent by the frame itself at `source`.
+    pub fn add_cost(&mut self, source: Option<SourceLocation>, gas: u64) {
+        let Some(source) = source else { return };
+        if gas == 0 {
+            return;
+        }
+        match self
+            .costs
+            .iter_mut()
+            .find(|cost| cost.source.same_range(&source))
+        {
+            Some(cost) => cost.gas += gas,
+            None => self.costs.push(SourceCost { source, gas }),
+        }
+    }
+
+    /// Returns the name of the frame as shown in flamegraphs, followed by the revert reason if
+    /// the frame reverted.
+    pub fn label(&self) -> String {
+        match &self.revert {
+            // `;` separates the frames of folded stack lines
+            Some(reason) => format!(
+                "{} [reverted: {}]",
+                self.name,
+                reason.replace(';', ",").replace('\n', " ")
+            ),
+            None => self.name.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CallNode {
+    pub id: NodeId,
+    /// Parent of the node, `None` for the root.
+    pub parent: Option<NodeId>,
+    /// Children of the node, in execution order.
+    pub children: Vec<NodeId>,
+    pub frame: Frame,
+}
+
+/// Call tree of a test, as built by the backends.
+///
+/// The first node is the root. Nodes are stored in the order they were added, so parents come
+/// before their children.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<CallNode>", into = "Vec<CallNode>")]
+pub struct CallTree {
+    nodes: Vec<CallNode>,
+}
+
+impl CallTree {
+    pub fn new(root: Frame) -> Self {
+        Self {
+            nodes: vec![CallNode {
+                id: NodeId(0),
+                parent: None,
+                children: vec![],
+                frame: root,
+            }],
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Adds `frame` as the last child of `parent` and returns its id.
+    pub fn push(&mut self, parent: NodeId, frame: Frame) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(CallNode {
+            id,
+            parent: Some(parent),
+            children: vec![],
+            frame,
+        });
+        self.nodes[parent.0].children.push(id);
+        id
+    }
+
+    /// Adds a copy of `tree` as the last child of `parent`, moved to start where the previous
+    /// child of `parent` ends, and extends `parent` up to its end. Returns the id of the copied
+    /// root.
+    pub fn append(&mut self, parent: NodeId, tree: &CallTree) -> NodeId {
+        let start = match self.node(parent).children.last() {
+            Some(last) => self.node(*last).frame.gas_end,
+            None => self.node(parent).frame.gas_start,
+        };
+        let origin = tree.node(tree.root()).frame.gas_start;
+        let mut ids: Vec<NodeId> = Vec::with_capacity(tree.nodes.len());
+        for node in &tree.nodes {
+            let frame = Frame {
+                gas_start: start + node.frame.gas_start.saturating_sub(origin),
+                gas_end: start + node.frame.gas_end.saturating_sub(origin),
+                ..node.frame.clone()
+            };
+            let parent = node.parent.map_or(parent, |parent| ids[parent.0]);
+            ids.push(self.push(parent, frame));
+        }
+
+        let end = self.node(ids[0]).frame.gas_end;
+        let parent = self.frame_mut(parent);
+        parent.gas_end = parent.gas_end.max(end);
+        ids[0]
+    }
+
+    /// Merges the trees of several runs of a test into a tree of the average gas per run.
+    ///
+    /// The roots are merged, and below them the frames with the same name under the same parent,
+    /// as flamegraphs merge stacks. The children are then ordered by first appearance instead of
+    /// execution. Returns `None` if there are no trees.
+    pub fn average(trees: &[CallTree]) -> Option<CallTree> {
+        let first = trees.first()?;
+        let root = first.node(first.root()).frame.clone();
+        let mut merged = CallTree::new(Frame {
+            instructions: None,
+            costs: vec![],
+            ..Frame::new(root.name, root.kind, 0)
+        });
+        merged.frame_mut(merged.root()).address = root.address;
+
+        let mut self_gas = vec![0_i64];
+        let mut merged_ids = HashMap::new();
+        for tree in trees {
+            let mut ids: Vec<NodeId> = Vec::with_capacity(tree.nodes.len());
+            for node in &tree.nodes {
+                let id = match node.parent {
+                    None => merged.root(),
+                    Some(parent) => {
+                        let parent = ids[parent.0];
+                        *merged_ids
+                            .entry((parent, node.frame.name.clone()))
+                            .or_insert_with(|| {
+                                self_gas.push(0);
+                                merged.push(
+                                    parent,
+                                    Frame {
+                                        address: node.frame.address,
+                                        source: node.frame.source,
+                                        revert: node.frame.revert.clone(),
+                                        ..Frame::new(node.frame.name.clone(), node.frame.kind, 0)
+                                    },
+                                )
+                            })
+                    }
+                };
+                self_gas[id.0] += tree.self_gas(node.id);
+                ids.push(id);
+            }
+        }
+
+        let runs = trees.len() as i64;
+        let self_gas = self_gas
+            .iter()
+            .map(|gas| ((gas + runs / 2) / runs) as u64)
+            .collect::<Vec<_>>();
+        merged.lay_out(&self_gas);
+        Some(merged)
+    }
+
+    /// Measures the frames in instructions instead of gas: the gas range of every frame becomes
+    /// the range of instructions it executed.
+    ///
+    /// Fails if a frame has no instruction count, only backends that step through the code
+    /// count instructions.
+    pub fn weigh_by_instructions(&mut self) -> eyre::Result<()> {
+        let instructions = self
+            .nodes
+            .iter()
+            .map(|node| {
+                node.frame
+                    .instructions
+                    .ok_or_else(|| eyre::eyre!("{} has no instruction count", node.frame.name))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        self.lay_out(&instructions);
+        Ok(())
+    }
+
+    /// Sets the ranges of the frames from the weight of each frame itself, the children laid
+    /// out one after the other from the start of their parent.
+    pub(crate) fn lay_out(&mut self, self_weights: &[u64]) {
+        // children come after their parents, so totals are summed up from the last node
+        let mut totals = vec![0_u64; self.nodes.len()];
+        for node in self.nodes.iter().rev() {
+            let children: u64 = node.children.iter().map(|child| totals[child.0]).sum();
+            totals[node.id.0] = self_weights[node.id.0] + children;
+        }
+        for i in 0..self.nodes.len() {
+            let frame = &mut self.nodes[i].frame;
+            frame.gas_end = frame.gas_start + totals[i];
+            let mut gas_start = frame.gas_start;
+            for child in self.nodes[i].children.clone() {
+                self.nodes[child.0].frame.gas_start = gas_start;
+                gas_start += totals[child.0];
+            }
+        }
+    }
+
+    /// Returns the node `id`.
+    ///
+    /// Panics if the node is not in the tree.
+    pub fn node(&self, id: NodeId) -> &CallNode {
+        &self.nodes[id.0]
+    }
+
+    pub fn frame_mut(&mut self, id: NodeId) -> &mut Frame {
+        &mut self.nodes[id.0].frame
+    }
+
+    /// Returns the frames of all the nodes, parents before their children.
+    pub fn frames_mut(&mut self) -> impl Iterator<Item = &mut Frame> {
+        self.nodes.iter_mut().map(|node| &mut node.frame)
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = &CallNode> {
+        self.node(id).children.iter().map(|child| self.node(*child))
+    }
+
+    /// Returns all the nodes, parents before their children.
+    pub fn nodes(&self) -> &[CallNode] {
+        &self.nodes
+    }
+
+    /// Returns the ids from `id` up to the root, both included.
+    pub fn path_to_root(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![id];
+        let mut current = id;
+        while let Some(parent) = self.parent(current) {
+            path.push(parent);
+            current = parent;
+        }
+        path
+    }
+
+    /// Returns the gas used by the node including its children.
+    pub fn total_gas(&self, id: NodeId) -> i64 {
+        let frame = &self.node(id).frame;
+        (frame.gas_end as i64) - (frame.gas_start as i64)
+    }
+
+    /// Returns the gas used by the node itself.
+    pub fn self_gas(&self, id: NodeId) -> i64 {
+        let child_gas: i64 = self
+            .node(id)
+            .children
+            .iter()
+            .map(|child| self.total_gas(*child))
+            .sum();
+        // children may use more gas than their parent, see `GasCheck::overlapping`
+        (self.total_gas(id) - child_gas).max(0)
+    }
+
+    /// Sets the line of the source locations of the tree.
+    pub fn resolve_lines(&mut self, lines: &LineIndex) {
+        for frame in self.frames_mut() {
+            let sources = frame
+                .source
+                .iter_mut()
+                .chain(frame.costs.iter_mut().map(|cost| &mut cost.source));
+            for source in sources {
+                source.line = source
+                    .index
+                    .and_then(|index| lines.line(index, source.offset));
+            }
+        }
+    }
+
+    /// Compares the gas of the tree with the gas reported by the test, and appends the gas
+    /// missing from the tree to the root as an [`UNATTRIBUTED`] frame.
+    pub fn reconcile_gas(&mut self, reported: u64) -> GasCheck {
+        let root = self.root();
+        let check = GasCheck {
+            reported,
+            profiled: self.total_gas(root),
+            overlapping: self.overlapping_gas(),
+        };
+        let missing = check.difference();
+        if missing > 0 {
+            let frame = &self.node(root).frame;
+            let gas_start = frame.gas_end.max(frame.gas_start);
+            let gas_end = gas_start + missing as u64;
+            let instructions = frame.instructions.map(|_| 0);
+            self.frame_mut(root).gas_end = gas_end;
+            self.push(
+                root,
+                Frame {
+                    gas_end,
+                    instructions,
+                    ..Frame::new(UNATTRIBUTED.to_string(), FrameKind::Unattributed, gas_start)
+                },
+            );
+        }
+        check
+    }
+
+    /// Returns the gas by which children exceed their parents in the tree.
+    fn overlapping_gas(&self) -> u64 {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let child_gas: i64 = node
+                    .children
+                    .iter()
+                    .map(|child| self.total_gas(*child))
+                    .sum();
+                (child_gas - self.total_gas(node.id)).max(0) as u64
+            })
+            .sum()
+    }
+
+    /// Returns the folded stack lines of the tree, one per frame, in the order inferno expects
+    /// for flame charts.
+    pub fn folded_stack_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        self.fold(self.root(), None, &mut lines);
+        lines.reverse();
+        lines
+    }
+
+    fn fold(&self, id: NodeId, prepend: Option<&str>, lines: &mut Vec<String>) {
+        let label = self.node(id).frame.label();
+        let stack = prepend
+            .map(|prepend| format!("{prepend};{label}"))
+            .unwrap_or(label);
+        lines.push(format!("{} {}", stack, self.self_gas(id)));
+        for child in &self.node(id).children {
+            self.fold(*child, Some(&stack), lines);
+        }
+    }
+}
+
+impl TryFrom<Vec<CallNode>> for CallTree {
+    type Error = String;
+
+    /// Checks that the nodes link to each other as [`CallTree::push`] links them.
+    fn try_from(nodes: Vec<CallNode>) -> Result<Self, Self::Error> {
+        if nodes.is_empty() {
+            return Err("call tree has no root".to_string());
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            if node.id.0 != i {
+                return Err(format!("node {i} has id {}", node.id.0));
+            }
+            match node.parent {
+                None if i != 0 => return Err(format!("node {i} has no parent")),
+                Some(parent) if parent.0 >= i => {
+                    return Err(format!("node {i} comes before its parent {}", parent.0))
+                }
+                Some(parent) if !nodes[parent.0].children.contains(&node.id) => {
+                    return Err(format!(
+                        "node {i} is not a child of its parent {}",
+                        parent.0
+                    ))
+                }
+                _ => {}
+            }
+            for child in &node.children {
+                if nodes.get(child.0).and_then(|child| child.parent) != Some(node.id) {
+                    return Err(format!("node {} is not a child of node {i}", child.0));
+                }
+            }
+        }
+        Ok(Self { nodes })
+    }
+}
+
+impl From<CallTree> for Vec<CallNode> {
+    fn from(tree: CallTree) -> Self {
+        tree.nodes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree() -> (CallTree, NodeId) {
+        let mut root = Frame::new("Vault.deposit".to_string(), FrameKind::Call, 0);
+        root.gas_end = 100;
+        let mut tree = CallTree::new(root);
+
+        let mut transfer = Frame::new("Token.transferFrom".to_string(), FrameKind::Call, 10);
+        transfer.gas_end = 40;
+        let transfer = tree.push(tree.root(), transfer);
+        let mut balance = Frame::new("Token._balance".to_string(), FrameKind::Internal, 20);
+        balance.gas_end = 30;
+        let balance = tree.push(transfer, balance);
+        (tree, balance)
+    }
+
+    #[test]
+    fn test_traversal() {
+        let (tree, balance) = tree();
+        assert_eq!(
+            tree.path_to_root(balance),
+            vec![balance, NodeId(1), tree.root()]
+        );
+        assert_eq!(tree.total_gas(tree.root()), 100);
+        assert_eq!(tree.self_gas(tree.root()), 70);
+        assert_eq!(tree.self_gas(NodeId(1)), 20);
+    }
+
+    #[test]
+    fn test_folded_stack_lines() {
+        let (mut tree, _) = tree();
+        let mut mint = Frame::new("_mint".to_string(), FrameKind::Internal, 50);
+        mint.gas_end = 70;
+        tree.push(tree.root(), mint);
+        tree.frame_mut(NodeId(1)).revert = Some("allowance; too low".to_string());
+        assert_eq!(
+            tree.folded_stack_lines(),
+            vec![
+                "Vault.deposit;_mint 20",
+                "Vault.deposit;Token.transferFrom [reverted: allowance, too low];Token._balance 10",
+                "Vault.deposit;Token.transferFrom [reverted: allowance, too low] 20",
+                "Vault.deposit 50",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reconcile_gas() {
+        let mut root = Frame::new("Vault.deposit".to_string(), FrameKind::Call, 0);
+        root.gas_end = 100;
+        let mut tree = CallTree::new(root);
+        let mut mint = Frame::new("_mint".to_string(), FrameKind::Internal, 10);
+        mint.gas_end = 120;
+        tree.push(tree.root(), mint);
+
+        let check = tree.clone().reconcile_gas(90);
+        assert_eq!(check.difference(), -10);
+
+        let check = tree.reconcile_gas(150);
+        assert_eq!(check.difference(), 50);
+        assert_eq!(check.overlapping, 10);
+        assert!(check.exceeds(1.0));
+        assert!(!check.exceeds(50.0));
+        assert_eq!(tree.total_gas(tree.root()), 150);
+        let unattributed = &tree.node(NodeId(2)).frame;
+        assert_eq!(unattributed.name, UNATTRIBUTED);
+        assert_eq!((unattributed.gas_start, unattributed.gas_end), (100, 150));
+    }
+
+    #[test]
+    fn test_append() {
+        let (tree, _) = tree();
+        let mut test = CallTree::new(Frame::new("VaultTest".to_string(), FrameKind::Call, 0));
+        test.append(test.root(), &tree);
+        let second = test.append(test.root(), &tree);
+
+        assert_eq!(test.total_gas(test.root()), 200);
+        assert_eq!(test.node(second).frame.gas_start, 100);
+        let balance = test.children(second).next().unwrap().children[0];
+        assert_eq!(test.node(balance).frame.gas_start, 120);
+        assert_eq!(test.path_to_root(balance).last(), Some(&test.root()));
+    }
+
+    #[test]
+    fn test_average() {
+        let (tree, _) = tree();
+        let mut cheap = CallTree::new(Frame::new("Vault.deposit".to_string(), FrameKind::Call, 0));
+        cheap.frame_mut(cheap.root()).gas_end = 50;
+
+        let average = CallTree::average(&[tree, cheap]).unwrap();
+        assert_eq!(average.nodes().len(), 3);
+        assert_eq!(average.total_gas(average.root()), 75);
+        // the transfer only ran once, its gas is spread over both runs
+        assert_eq!(average.total_gas(NodeId(1)), 15);
+        assert_eq!(average.self_gas(average.root()), 60);
+
+        assert_eq!(average.node(NodeId(2)).frame.gas_start, 0);
+        assert_eq!(CallTree::average(&[]), None);
+    }
+
+    #[test]
+    fn test_weigh_by_instructions() {
+        let (mut tree, balance) = tree();
+        assert!(tree.clone().weigh_by_instructions().is_err());
+
+        for (id, instructions) in [(tree.root(), 7), (NodeId(1), 2), (balance, 40)] {
+            tree.frame_mut(id).instructions = Some(instructions);
+        }
+        tree.weigh_by_instructions().unwrap();
+        assert_eq!(tree.total_gas(tree.root()), 49);
+        assert_eq!(tree.total_gas(NodeId(1)), 42);
+        assert_eq!(tree.self_gas(balance), 40);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<CallTree>();
+
+        let (tree, _) = tree();
+        let json = serde_json::to_string(&tree).unwrap();
+        assert_eq!(serde_json::from_str::<CallTree>(&json).unwrap(), tree);
+
+        // a node pointing to a parent that doesn't list it
+        let mut nodes = Vec::from(tree);
+        nodes[0].children.clear();
+        let json = serde_json::to_string(&nodes).unwrap();
+        assert!(serde_json::from_str::<CallTree>(&json).is_err());
+    }
+}